@@ -1,39 +1,63 @@
 //! AI Agent
 //! An instance of the Game played by an AI
 
-use nn::Net;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
 
-use crate::game::Game;
-use crate::{get_eight_dirs, nn, FourDirs, Point, NN_ARCH, NUM_STEPS};
+use nn::Net;
+use serde::{Deserialize, Serialize};
+
+use crate::game::{Game, GameOutcome};
+use crate::sensors::{Sensor, View};
+use crate::{nn, FourDirs, SimConfig};
+
+/// How the net outputs are turned into a move
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionSpace {
+    /// One output per direction, including the one reversing into the neck
+    #[default]
+    Absolute,
+    /// Turn left, go straight or turn right
+    Relative,
+}
 
 #[derive(Clone)]
 pub struct Agent {
     pub game: Game,
     pub brain: Net,
+
+    sensors: Arc<Vec<Box<dyn Sensor>>>,
+    num_steps: usize,
 }
 
-impl Agent {
+impl ActionSpace {
     #[must_use]
-    pub fn new(is_load: bool) -> Self {
-        let brain = if is_load {
-            let mut net = Net::load();
-            net.mutate(0.0, 0.1);
-            net
-        } else {
-            Net::new(&NN_ARCH)
-        };
-
-        Self {
-            game: Game::new(),
-            brain,
+    pub fn num_outputs(self) -> usize {
+        match self {
+            Self::Absolute => 4,
+            Self::Relative => 3,
         }
     }
+}
 
+impl Agent {
+    /// `seed` drives the game the agent plays
     #[must_use]
-    pub fn with_brain(brain: Net) -> Self {
+    pub fn with_brain(brain: Net, config: &SimConfig, seed: u64) -> Self {
+        let sensors = brain
+            .meta()
+            .sensors
+            .iter()
+            .map(|s| s.build(&config.game))
+            .collect();
+
         Self {
-            game: Game::new(),
+            game: Game::new(&config.game, seed),
             brain,
+            sensors: Arc::new(sensors),
+            num_steps: config.sim.num_steps,
         }
     }
 
@@ -44,135 +68,89 @@ impl Agent {
 
         self.game.update(self.get_brain_output());
 
-        // Limit the number of steps the snake can take without eating
+        // Limit the number of steps the snake can take without eating,
+        // a snake that just crashed keeps its cause of death
         let step_limit = self.get_step_limit();
-        if self.game.no_food_steps >= step_limit {
-            self.game.is_dead = true;
+        if !self.game.is_dead && self.game.no_food_steps >= step_limit {
+            self.game.end(GameOutcome::Starved);
         }
 
         true
     }
 
-    #[must_use]
-    pub fn fitness(&self) -> f32 {
-        let score = self.game.body.len() as f32;
-        if score <= 1.0 {
-            return 1.0;
-        }
-
-        let mut fitness = 1.0;
-        if score < 5.0 {
-            fitness *= 2.0_f32.powf(score);
-            fitness *= score;
-            fitness *= self.game.total_steps as f32 * 0.1;
-        } else {
-            fitness *= score * score * score;
-            fitness *= self.game.total_steps as f32 * 0.1;
-        }
-
-        fitness
-    }
-
     #[must_use]
     pub fn get_brain_output(&self) -> FourDirs {
         let vision = self.get_brain_input();
         let nn_out = self.brain.predict(vision);
-        let (l, r, b, t) = (nn_out[0], nn_out[1], nn_out[2], nn_out[3]);
-        let mut directions = [
-            (l, FourDirs::Left),
-            (r, FourDirs::Right),
-            (b, FourDirs::Bottom),
-            (t, FourDirs::Top),
-        ];
+        let heading = self.game.dir;
+        let mut directions = match self.brain.meta().actions {
+            ActionSpace::Absolute => vec![
+                (nn_out[0], FourDirs::Left),
+                (nn_out[1], FourDirs::Right),
+                (nn_out[2], FourDirs::Bottom),
+                (nn_out[3], FourDirs::Top),
+            ],
+            ActionSpace::Relative => vec![
+                (nn_out[0], heading.turn_left()),
+                (nn_out[1], heading),
+                (nn_out[2], heading.turn_right()),
+            ],
+        };
         directions.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
         directions[0].1
     }
 
+    /// Egocentric inputs are rotated so that straight ahead is always `Top`
     #[must_use]
     pub fn get_brain_input(&self) -> Vec<f64> {
-        let dirs = get_eight_dirs().to_vec();
-        let vision = self.get_snake_vision(dirs);
-        let head_dir = self.game.dir.get_one_hot_dir();
-        let tail_dir = self.get_tail_direction().get_one_hot_dir();
-
-        vision.into_iter().chain(head_dir).chain(tail_dir).collect()
-    }
-
-    fn get_snake_vision(&self, dirs: Vec<(i32, i32)>) -> Vec<f64> {
-        let mut vision = Vec::new();
-
-        for d in dirs {
-            // Food and Body are one hot
-            let (solid, food) = self.vision_in_dir(self.game.head, d);
-            vision.push(f64::from(solid));
-            vision.push(if food { 1.0 } else { 0.0 });
-        }
-
-        vision
-    }
-
-    fn vision_in_dir(&self, st: Point, dir: (i32, i32)) -> (f32, bool) {
-        let mut food = false;
-        let mut temp_pt: Point = st;
-        let mut dist = 0;
-
-        loop {
-            if self.game.is_wall(temp_pt) || self.game.is_snake_body(temp_pt) {
-                break;
-            }
-
-            if self.game.food == temp_pt {
-                food = true;
-            }
-
-            temp_pt = Point::new(temp_pt.x + dir.0, temp_pt.y + dir.1);
-
-            dist += 1;
-            if dist > 1000 {
-                break;
-            }
+        let view = View {
+            game: &self.game,
+            egocentric: self.brain.meta().egocentric,
+        };
+        let mut inputs = Vec::with_capacity(self.brain.num_inputs());
+        for sensor in self.sensors.iter() {
+            sensor.sense(&view, &mut inputs);
         }
 
-        (1.0 / dist as f32, food)
+        inputs
     }
 
     #[must_use]
     pub fn get_step_limit(&self) -> usize {
-        match self.game.score() {
-            score if score > 30 => NUM_STEPS * 6,
-            score if score > 20 => NUM_STEPS * 3,
-            score if score > 5 => NUM_STEPS * 2,
-            _ => NUM_STEPS,
-        }
+        Self::step_limit(self.game.score(), self.num_steps)
     }
 
-    fn get_tail_direction(&self) -> FourDirs {
-        if let Some(tail) = self.game.body.last() {
-            if let Some(body) = self.game.body.get(self.game.body.len() - 2) {
-                let x = body.x - tail.x;
-                let y = body.y - tail.y;
-
-                return match (x, y) {
-                    (-1, 0) => FourDirs::Left,
-                    (1, 0) => FourDirs::Right,
-                    (0, 1) => FourDirs::Bottom,
-                    _ => FourDirs::Top,
-                };
-            }
+    /// Steps a snake of `score` can take without eating, longer snakes get more
+    #[must_use]
+    pub fn step_limit(score: usize, num_steps: usize) -> usize {
+        match score {
+            score if score > 30 => num_steps * 6,
+            score if score > 20 => num_steps * 3,
+            score if score > 5 => num_steps * 2,
+            _ => num_steps,
         }
-
-        self.game.dir
     }
 }
 
-impl PartialEq for Agent {
-    fn eq(&self, other: &Self) -> bool {
-        self.fitness() == other.fitness()
+impl FromStr for ActionSpace {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "absolute" => Ok(Self::Absolute),
+            "relative" => Ok(Self::Relative),
+            _ => Err(format!(
+                "unknown action space {s:?}, expected absolute or relative"
+            )),
+        }
     }
 }
 
-impl PartialOrd for Agent {
-    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.fitness().partial_cmp(&other.fitness())
+impl fmt::Display for ActionSpace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Absolute => write!(f, "absolute"),
+            Self::Relative => write!(f, "relative"),
+        }
     }
 }
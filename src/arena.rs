@@ -0,0 +1,401 @@
+//! Arena
+//! Several snakes, each driven by its own net, competing for the food on one board
+//!
+//! Every snake is an agent with its own game, the arena places the one food
+//! they share and tells each game where the others are. Collisions are checked
+//! once every snake has moved, so a tail that moved away is free like it is for
+//! the snake itself. Running into another snake ends the game with `HitSnake`,
+//! heads meeting end the shorter snake, or both when they are the same length.
+//! Dead snakes are taken off the board
+
+use std::io::{self, ErrorKind};
+
+use rand::{Rng, SeedableRng};
+
+use crate::agent::Agent;
+use crate::game::{Game, GameOutcome, GameRecord};
+use crate::nn::Net;
+use crate::{FourDirs, Point, SimConfig, SimRng};
+
+pub struct Arena {
+    pub snakes: Vec<Agent>,
+    pub food: Point,
+    width: i32,
+    height: i32,
+    rng: SimRng,
+}
+
+impl Arena {
+    /// Snakes start on their own rows, every other one facing the other way
+    pub fn new(nets: &[Net], config: &SimConfig, seed: u64) -> io::Result<Self> {
+        let (width, height) = (config.game.width, config.game.height);
+        if !config.game.maps.is_empty() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "the arena is played without levels",
+            ));
+        }
+        let max_snakes = ((height - 1) / 2) as usize;
+        if nets.len() < 2 || nets.len() > max_snakes {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "a {width}x{height} arena takes 2 to {max_snakes} snakes, got {}",
+                    nets.len()
+                ),
+            ));
+        }
+
+        let num_snakes = nets.len() as i32;
+        let snakes = nets
+            .iter()
+            .zip(0..num_snakes)
+            .map(|(net, i)| {
+                let y = (i + 1) * height / (num_snakes + 1);
+                let (start_dir, behind) = if i % 2 == 0 {
+                    (FourDirs::Right, -1)
+                } else {
+                    (FourDirs::Left, 1)
+                };
+                let start_body = (0..Game::START_LEN as i32)
+                    .map(|j| Point::new(width / 2 + behind * j, y))
+                    .collect();
+
+                // The food is set once every snake is on the board
+                let mut agent = Agent::with_brain(net.clone(), config, seed);
+                agent.game = Game::from_record(
+                    &config.game,
+                    None,
+                    &GameRecord {
+                        start_body,
+                        start_dir,
+                        foods: vec![Point::default()],
+                        moves: Vec::new(),
+                    },
+                );
+                agent.game.set_external_food();
+                agent
+            })
+            .collect();
+
+        let mut arena = Self {
+            snakes,
+            food: Point::default(),
+            width,
+            height,
+            rng: SimRng::seed_from_u64(seed),
+        };
+        arena.place_food();
+        arena.update_others();
+
+        Ok(arena)
+    }
+
+    /// Moves every living snake once, returns false once they are all dead
+    pub fn update(&mut self) -> bool {
+        let alive: Vec<usize> = (0..self.snakes.len())
+            .filter(|&i| !self.snakes[i].game.is_dead)
+            .collect();
+        if alive.is_empty() {
+            return false;
+        }
+
+        let before = self.tick_start(&alive);
+        for &i in &alive {
+            self.snakes[i].update();
+        }
+        self.end_tick(&before);
+
+        true
+    }
+
+    #[must_use]
+    pub fn is_over(&self) -> bool {
+        self.snakes.iter().all(|snake| snake.game.is_dead)
+    }
+
+    /// The snake with the highest score, `None` on a tie
+    #[must_use]
+    pub fn winner(&self) -> Option<usize> {
+        let best = self.snakes.iter().map(|s| s.game.score()).max()?;
+        let leaders: Vec<usize> = (0..self.snakes.len())
+            .filter(|&i| self.snakes[i].game.score() == best)
+            .collect();
+        match leaders.as_slice() {
+            [winner] => Some(*winner),
+            _ => None,
+        }
+    }
+
+    /// The living snake on a cell and whether the cell is its head
+    #[must_use]
+    pub fn snake_at(&self, pt: Point) -> Option<(usize, bool)> {
+        self.snakes
+            .iter()
+            .enumerate()
+            .filter(|(_, snake)| !snake.game.is_dead)
+            .find_map(|(i, snake)| {
+                snake
+                    .game
+                    .body
+                    .iter()
+                    .position(|&cell| cell == pt)
+                    .map(|pos| (i, pos == 0))
+            })
+    }
+
+    fn end(&mut self, idx: usize) {
+        let game = &mut self.snakes[idx].game;
+        if !game.is_dead {
+            game.end(GameOutcome::HitSnake);
+        }
+    }
+
+    // The snakes about to move, with their head and score
+    fn tick_start(&self, alive: &[usize]) -> Vec<(usize, Point, usize)> {
+        alive
+            .iter()
+            .map(|&i| (i, self.snakes[i].game.head, self.snakes[i].game.score()))
+            .collect()
+    }
+
+    // Every snake that moved is still on the board for this tick,
+    // even when it just crashed on its own
+    fn end_tick(&mut self, before: &[(usize, Point, usize)]) {
+        let mut crashed = Vec::new();
+        for &(i, old_head, _) in before {
+            let game = &self.snakes[i].game;
+            if game.is_dead {
+                continue;
+            }
+
+            for &(j, other_old_head, _) in before.iter().filter(|&&(j, ..)| j != i) {
+                let other = &self.snakes[j].game;
+
+                // Heads meeting on a cell or passing through each other
+                let is_head_on = game.head == other.head
+                    || (game.head == other_old_head && other.head == old_head);
+                if is_head_on {
+                    if game.score() <= other.score() {
+                        crashed.push(i);
+                    }
+                } else if other.body[1..].contains(&game.head) {
+                    crashed.push(i);
+                }
+            }
+        }
+        for i in crashed {
+            self.end(i);
+        }
+
+        // Snakes grow the step after their head reaches the food
+        let has_eaten = before
+            .iter()
+            .any(|&(i, _, score)| self.snakes[i].game.score() > score);
+        if has_eaten {
+            self.place_food();
+        }
+        self.update_others();
+    }
+
+    // Each snake sees the living others as solid
+    fn update_others(&mut self) {
+        let bodies: Vec<&[Point]> = self
+            .snakes
+            .iter()
+            .map(|snake| {
+                if snake.game.is_dead {
+                    &[][..]
+                } else {
+                    &snake.game.body[..]
+                }
+            })
+            .collect();
+        let others: Vec<Vec<Point>> = (0..bodies.len())
+            .map(|i| {
+                bodies
+                    .iter()
+                    .enumerate()
+                    .filter(|&(j, _)| j != i)
+                    .flat_map(|(_, body)| body.iter().copied())
+                    .collect()
+            })
+            .collect();
+
+        for (snake, cells) in self.snakes.iter_mut().zip(others) {
+            snake.game.set_others(cells);
+        }
+    }
+
+    // A free cell away from every living snake, the food stays put on a full board
+    fn place_food(&mut self) {
+        let free_cells: Vec<Point> = (1..self.height)
+            .flat_map(|y| (1..self.width).map(move |x| Point::new(x, y)))
+            .filter(|&pt| self.snake_at(pt).is_none())
+            .collect();
+        if free_cells.is_empty() {
+            return;
+        }
+
+        self.food = free_cells[self.rng.gen_range(0..free_cells.len())];
+        for snake in &mut self.snakes {
+            snake.game.food = self.food;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+
+    fn arena(num_snakes: usize) -> Arena {
+        let config = SimConfig::default();
+        let mut rng = SimRng::seed_from_u64(7);
+        let nets: Vec<Net> = (0..num_snakes)
+            .map(|_| {
+                Net::new(&config.nn.arch, &config.nn.activations, &mut rng)
+                    .with_meta(config.nn.meta())
+            })
+            .collect();
+        let mut arena = Arena::new(&nets, &config, 7).unwrap();
+
+        // Out of the way of the snakes the tests put on the board
+        arena.food = Point::new(1, 1);
+        for snake in &mut arena.snakes {
+            snake.game.food = arena.food;
+        }
+        arena
+    }
+
+    fn place(arena: &mut Arena, idx: usize, body: &[(i32, i32)], dir: FourDirs) {
+        let game = &mut arena.snakes[idx].game;
+        game.body = body.iter().map(|&pt| pt.into()).collect();
+        game.head = game.body[0];
+        game.dir = dir;
+        arena.update_others();
+    }
+
+    // One move per snake, without asking the nets
+    fn tick(arena: &mut Arena, dirs: &[FourDirs]) {
+        let alive: Vec<usize> = (0..arena.snakes.len()).collect();
+        let before = arena.tick_start(&alive);
+        for (snake, &dir) in arena.snakes.iter_mut().zip(dirs) {
+            snake.game.update(dir);
+        }
+        arena.end_tick(&before);
+    }
+
+    fn outcomes(arena: &Arena) -> Vec<GameOutcome> {
+        arena.snakes.iter().map(|s| s.game.outcome).collect()
+    }
+
+    #[test]
+    fn heads_meeting_end_both_snakes_of_the_same_length() {
+        let mut arena = arena(2);
+        place(&mut arena, 0, &[(5, 7), (4, 7), (3, 7)], FourDirs::Right);
+        place(&mut arena, 1, &[(7, 7), (8, 7), (9, 7)], FourDirs::Left);
+
+        tick(&mut arena, &[FourDirs::Right, FourDirs::Left]);
+        assert_eq!(outcomes(&arena), [GameOutcome::HitSnake; 2]);
+        assert!(arena.is_over());
+    }
+
+    #[test]
+    fn heads_meeting_end_the_shorter_snake() {
+        let mut arena = arena(2);
+        place(&mut arena, 0, &[(5, 7), (4, 7), (3, 7)], FourDirs::Right);
+        place(
+            &mut arena,
+            1,
+            &[(7, 7), (8, 7), (9, 7), (10, 7)],
+            FourDirs::Left,
+        );
+
+        tick(&mut arena, &[FourDirs::Right, FourDirs::Left]);
+        assert_eq!(
+            outcomes(&arena),
+            [GameOutcome::HitSnake, GameOutcome::Running]
+        );
+    }
+
+    #[test]
+    fn heads_passing_through_each_other_meet() {
+        let mut arena = arena(2);
+        place(
+            &mut arena,
+            0,
+            &[(6, 7), (5, 7), (4, 7), (3, 7)],
+            FourDirs::Right,
+        );
+        place(&mut arena, 1, &[(7, 7), (8, 7), (9, 7)], FourDirs::Left);
+
+        tick(&mut arena, &[FourDirs::Right, FourDirs::Left]);
+        assert_eq!(
+            outcomes(&arena),
+            [GameOutcome::Running, GameOutcome::HitSnake]
+        );
+    }
+
+    #[test]
+    fn running_into_a_body_ends_the_snake() {
+        let mut arena = arena(2);
+        place(&mut arena, 0, &[(5, 7), (5, 6), (5, 5)], FourDirs::Top);
+        place(&mut arena, 1, &[(6, 8), (5, 8), (4, 8)], FourDirs::Right);
+
+        tick(&mut arena, &[FourDirs::Top, FourDirs::Right]);
+        assert_eq!(
+            outcomes(&arena),
+            [GameOutcome::HitSnake, GameOutcome::Running]
+        );
+    }
+
+    #[test]
+    fn a_tail_that_moved_away_is_free() {
+        let mut arena = arena(2);
+        place(&mut arena, 0, &[(4, 7), (4, 6), (4, 5)], FourDirs::Top);
+        place(&mut arena, 1, &[(6, 8), (5, 8), (4, 8)], FourDirs::Right);
+
+        tick(&mut arena, &[FourDirs::Top, FourDirs::Right]);
+        assert_eq!(outcomes(&arena), [GameOutcome::Running; 2]);
+        assert_eq!(arena.snake_at(Point::new(4, 8)), Some((0, true)));
+    }
+
+    #[test]
+    fn a_growing_tail_stays() {
+        let mut arena = arena(2);
+        place(&mut arena, 0, &[(4, 7), (4, 6), (4, 5)], FourDirs::Top);
+        place(&mut arena, 1, &[(6, 8), (5, 8), (4, 8)], FourDirs::Right);
+        arena.snakes[1].game.food = Point::new(6, 8);
+
+        tick(&mut arena, &[FourDirs::Top, FourDirs::Right]);
+        assert_eq!(arena.snakes[1].game.score(), 4);
+        assert_eq!(
+            outcomes(&arena),
+            [GameOutcome::HitSnake, GameOutcome::Running]
+        );
+    }
+
+    #[test]
+    fn food_is_placed_by_the_arena_only() {
+        let mut arena = arena(2);
+        place(&mut arena, 0, &[(4, 4), (3, 4), (2, 4)], FourDirs::Right);
+        place(&mut arena, 1, &[(4, 10), (3, 10), (2, 10)], FourDirs::Right);
+        arena.food = Point::new(5, 4);
+        for snake in &mut arena.snakes {
+            snake.game.food = arena.food;
+        }
+
+        for _ in 0..2 {
+            tick(&mut arena, &[FourDirs::Right, FourDirs::Right]);
+        }
+        assert_eq!(arena.snakes[0].game.score(), 4);
+        assert_ne!(arena.food, Point::new(5, 4));
+        assert_eq!(arena.snake_at(arena.food), None);
+        for snake in &arena.snakes {
+            assert_eq!(snake.game.food, arena.food);
+            assert_eq!(snake.game.record().foods.len(), 1);
+        }
+    }
+}
@@ -0,0 +1,48 @@
+//! Checkpoint
+//! Full snapshot of a simulation, used to resume a run exactly where it left off
+
+use std::{
+    fs::{self, create_dir_all},
+    io,
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::curriculum::CurriculumState;
+use crate::nn::Net;
+use crate::pop::PopulationState;
+use crate::sim::GenerationSummary;
+use crate::SimConfig;
+
+#[derive(Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// The seed of the run is stored in the config
+    pub config: SimConfig,
+    pub pop: PopulationState,
+    pub max_score: usize,
+    pub best_net: Option<Net>,
+    pub history: Vec<GenerationSummary>,
+    #[serde(default)]
+    pub curriculum: CurriculumState,
+}
+
+impl Checkpoint {
+    /// Written to a temp file first so a crash mid write
+    /// never leaves a corrupt checkpoint behind
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let path = Path::new(path);
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, serde_json::to_string(self)?)?;
+        fs::rename(tmp_path, path)
+    }
+
+    pub fn load(path: &str) -> io::Result<Self> {
+        let buff = fs::read_to_string(path)?;
+        Ok(serde_json::from_str(&buff)?)
+    }
+}
@@ -0,0 +1,340 @@
+//! Command line interface
+//! Subcommands and flags that override the default configs
+
+use std::io;
+
+use clap::{Args, Parser, Subcommand};
+
+use crate::agent::ActionSpace;
+use crate::curriculum::CurriculumStage;
+use crate::fitness::FitnessKind;
+use crate::mutation::ScheduleKind;
+use crate::nn::{Activation, CrossoverKind, MutationKind};
+use crate::reproduction::StageConfig;
+use crate::sensors::SensorKind;
+use crate::SimConfig;
+
+#[derive(Parser)]
+#[command(
+    name = "snake-tui",
+    version,
+    about = "A neural network learns to play snake"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+
+    /// Toml or json config file, flags take precedence over its values
+    #[arg(long, global = true)]
+    pub config_file: Option<String>,
+
+    #[command(flatten)]
+    pub config: ConfigArgs,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Evolve a population of snakes
+    Train(TrainArgs),
+    /// Watch a saved network play in the terminal
+    Watch(NetArgs),
+    /// Play a saved network over many games and print score stats
+    Eval(EvalArgs),
+    /// Continue training from a checkpoint, flags override its config
+    Resume(ResumeArgs),
+    /// Print the resolved config, or write it to a file
+    Config(ConfigFileArgs),
+    /// Play back a recorded game
+    Replay(ReplayArgs),
+    /// Play snake yourself with the arrow keys or WASD
+    Play(PlayArgs),
+    /// Play against a saved network, side by side on the same board
+    Versus(VersusArgs),
+    /// Watch saved networks compete for the food on one board
+    Arena(ArenaArgs),
+}
+
+#[derive(Args)]
+pub struct TrainArgs {
+    /// Run without the terminal ui, progress is printed once per generation
+    #[arg(long)]
+    pub headless: bool,
+
+    /// Stop after this many generations
+    #[arg(long)]
+    pub max_gens: Option<usize>,
+}
+
+#[derive(Args)]
+pub struct ResumeArgs {
+    /// Checkpoint file, defaults to the configured checkpoint file
+    #[arg(long)]
+    pub checkpoint: Option<String>,
+
+    #[command(flatten)]
+    pub train: TrainArgs,
+}
+
+#[derive(Args)]
+pub struct NetArgs {
+    /// Network file, defaults to the configured load file
+    #[arg(long)]
+    pub net: Option<String>,
+}
+
+#[derive(Args)]
+pub struct EvalArgs {
+    #[command(flatten)]
+    pub net: NetArgs,
+
+    /// Number of games to play
+    #[arg(long, default_value_t = 100)]
+    pub games: usize,
+}
+
+#[derive(Args)]
+pub struct ReplayArgs {
+    /// Replay file, eg. data/replays/gen-10.json
+    pub file: String,
+}
+
+#[derive(Args)]
+pub struct PlayArgs {
+    /// Milliseconds between moves
+    #[arg(long, default_value_t = 120)]
+    pub tick_ms: u64,
+}
+
+#[derive(Args)]
+pub struct VersusArgs {
+    #[command(flatten)]
+    pub net: NetArgs,
+
+    #[command(flatten)]
+    pub play: PlayArgs,
+}
+
+#[derive(Args)]
+pub struct ArenaArgs {
+    /// Network files, one snake each, eg. data/net-100.json,data/net-100-2.json
+    #[arg(long, value_delimiter = ',', required = true)]
+    pub nets: Vec<String>,
+
+    /// Milliseconds between moves
+    #[arg(long, default_value_t = 60)]
+    pub tick_ms: u64,
+
+    /// Play many rounds without the terminal ui and print the results
+    #[arg(long)]
+    pub headless: bool,
+
+    /// Number of rounds to play when headless
+    #[arg(long, default_value_t = 100)]
+    pub games: usize,
+}
+
+#[derive(Args)]
+pub struct ConfigFileArgs {
+    /// Toml or json output file
+    #[arg(long)]
+    pub out: Option<String>,
+}
+
+/// Every field is optional, only the flags that were passed override the config
+#[derive(Args)]
+pub struct ConfigArgs {
+    // Game
+    /// Sets both the width and height of the board
+    #[arg(long, global = true, help_heading = "Game")]
+    pub grid_size: Option<i32>,
+    #[arg(long, global = true, help_heading = "Game")]
+    pub grid_width: Option<i32>,
+    #[arg(long, global = true, help_heading = "Game")]
+    pub grid_height: Option<i32>,
+    /// Moving off one edge of the board comes back in on the other
+    #[arg(long, global = true, help_heading = "Game")]
+    pub wrap: bool,
+    /// Level names from data/levels or level file paths, each game plays one
+    /// of them and the board size comes from the levels
+    #[arg(long, global = true, value_delimiter = ',', help_heading = "Game")]
+    pub levels: Option<Vec<String>>,
+
+    // Sim
+    #[arg(long, global = true, help_heading = "Sim")]
+    pub num_agents: Option<usize>,
+    #[arg(long, global = true, help_heading = "Sim")]
+    pub num_steps: Option<usize>,
+    #[arg(long, global = true, help_heading = "Sim")]
+    pub num_threads: Option<usize>,
+    /// Seed for a reproducible run, independent of the number of threads
+    #[arg(long, global = true, help_heading = "Sim")]
+    pub seed: Option<u64>,
+    /// Board sizes to train on in order, a stage ends once a generation reaches
+    /// the score or after the gens, eg. 8x8:score(20),10x10:gens(100),15x15
+    #[arg(long, global = true, value_delimiter = ',', help_heading = "Sim")]
+    pub curriculum: Option<Vec<CurriculumStage>>,
+
+    // Pop
+    /// Reproduction stages as op[:selection]=fraction,
+    /// eg. elite=0.1,crossover:roulette=0.6,mutate:tournament(6)=0.1,random=0.2
+    #[arg(long, global = true, value_delimiter = ',', help_heading = "Pop")]
+    pub pop_pipeline: Option<Vec<StageConfig>>,
+    /// How crossover stages combine two parents,
+    /// uniform, single_point, multi_point(points), node, blend(alpha) or none
+    #[arg(long, global = true, help_heading = "Pop")]
+    pub crossover: Option<CrossoverKind>,
+    /// uniform, gaussian, reset or self_adaptive
+    #[arg(long, global = true, help_heading = "Pop")]
+    pub mutation: Option<MutationKind>,
+    /// How the mutation rate and magnitude change over a run, constant(rate,magnitude),
+    /// decay(rate,magnitude,decay,floor), score_banded or stagnation(patience,boost)
+    #[arg(long, global = true, help_heading = "Pop")]
+    pub mutation_schedule: Option<ScheduleKind>,
+    /// classic, score, step_penalty, food_per_step or survival
+    #[arg(long, global = true, help_heading = "Pop")]
+    pub fitness: Option<FitnessKind>,
+
+    // Data
+    #[arg(long, global = true, help_heading = "Data")]
+    pub save_file: Option<String>,
+    #[arg(long, global = true, help_heading = "Data")]
+    pub load_file: Option<String>,
+    /// Seed the population with the network from the load file
+    #[arg(long, global = true, help_heading = "Data")]
+    pub load_net: bool,
+    /// Save the best network of the simulation to the save file
+    #[arg(long, global = true, help_heading = "Data")]
+    pub save_net: bool,
+    #[arg(long, global = true, help_heading = "Data")]
+    pub checkpoint_file: Option<String>,
+    /// Write a checkpoint every N generations and on exit, 0 disables checkpoints
+    #[arg(long, global = true, help_heading = "Data")]
+    pub checkpoint_every: Option<usize>,
+    /// Append per generation metrics to a .csv or .jsonl file
+    #[arg(long, global = true, help_heading = "Data")]
+    pub metrics_file: Option<String>,
+    /// Record the best game of every generation to this directory
+    #[arg(long, global = true, help_heading = "Data")]
+    pub replay_dir: Option<String>,
+
+    // NN
+    /// Layer sizes, eg. 24,16,8,4. When neither this nor the config file sets it
+    /// the input and output sizes are derived from the sensors and actions
+    #[arg(long, global = true, value_delimiter = ',', help_heading = "NN")]
+    pub nn_arch: Option<Vec<usize>>,
+    /// Activation per layer after the input, eg. relu,relu,softmax.
+    /// One of relu, leaky_relu, tanh, sigmoid, linear or softmax
+    #[arg(long, global = true, value_delimiter = ',', help_heading = "NN")]
+    pub nn_activations: Option<Vec<Activation>>,
+    /// Absolute needs 4 outputs, relative (left, straight, right) needs 3
+    #[arg(long, global = true, help_heading = "NN")]
+    pub nn_actions: Option<ActionSpace>,
+    /// Feed the inputs relative to the snake heading
+    #[arg(long, global = true, help_heading = "NN")]
+    pub nn_egocentric: bool,
+    /// Inputs of the net in order, vision, wall_rays, body_rays, food_rays,
+    /// food_delta, length, window(size), grid, head_dir, tail_dir and flood_fill
+    #[arg(long, global = true, value_delimiter = ',', help_heading = "NN")]
+    pub nn_sensors: Option<Vec<SensorKind>>,
+
+    // Viz
+    #[arg(long, global = true, help_heading = "Viz")]
+    pub low_detail: bool,
+    #[arg(long, global = true, help_heading = "Viz")]
+    pub game_canvas: bool,
+    #[arg(long, global = true, help_heading = "Viz")]
+    pub viz_game_scale: Option<i32>,
+    #[arg(long, global = true, help_heading = "Viz")]
+    pub viz_offset: Option<i32>,
+    #[arg(long, global = true, help_heading = "Viz")]
+    pub viz_update_frames: Option<u32>,
+    #[arg(long, global = true, help_heading = "Viz")]
+    pub viz_graphs_len: Option<usize>,
+}
+
+impl ConfigArgs {
+    pub fn apply(&self, config: &mut SimConfig) {
+        fn set<T: Clone>(field: &mut T, value: &Option<T>) {
+            if let Some(value) = value {
+                *field = value.clone();
+            }
+        }
+
+        set(&mut config.game.width, &self.grid_size);
+        set(&mut config.game.height, &self.grid_size);
+        set(&mut config.game.width, &self.grid_width);
+        set(&mut config.game.height, &self.grid_height);
+        config.game.wrap |= self.wrap;
+        set(&mut config.game.levels, &self.levels);
+
+        set(&mut config.sim.num_agents, &self.num_agents);
+        set(&mut config.sim.num_steps, &self.num_steps);
+        set(&mut config.sim.num_threads, &self.num_threads);
+        set(&mut config.sim.curriculum, &self.curriculum);
+        if self.seed.is_some() {
+            config.sim.seed = self.seed;
+        }
+
+        set(&mut config.pop.pipeline, &self.pop_pipeline);
+        set(&mut config.pop.crossover, &self.crossover);
+        set(&mut config.pop.mutation, &self.mutation);
+        set(&mut config.pop.mutation_schedule, &self.mutation_schedule);
+        set(&mut config.pop.fitness, &self.fitness);
+
+        set(&mut config.data.save_file_name, &self.save_file);
+        set(&mut config.data.load_file_name, &self.load_file);
+        config.data.is_load_saved_data |= self.load_net;
+        config.data.is_save_best_net |= self.save_net;
+        set(&mut config.data.checkpoint_file_name, &self.checkpoint_file);
+        set(&mut config.data.checkpoint_every, &self.checkpoint_every);
+        if self.metrics_file.is_some() {
+            config.data.metrics_file_name.clone_from(&self.metrics_file);
+        }
+        if self.replay_dir.is_some() {
+            config.data.replay_dir.clone_from(&self.replay_dir);
+        }
+
+        set(&mut config.nn.arch, &self.nn_arch);
+        set(&mut config.nn.activations, &self.nn_activations);
+        set(&mut config.nn.actions, &self.nn_actions);
+        config.nn.egocentric |= self.nn_egocentric;
+        set(&mut config.nn.sensors, &self.nn_sensors);
+
+        config.viz.is_low_detail_mode |= self.low_detail;
+        config.viz.use_game_canvas |= self.game_canvas;
+        set(&mut config.viz.game_scale, &self.viz_game_scale);
+        set(&mut config.viz.offset, &self.viz_offset);
+        set(&mut config.viz.update_frames, &self.viz_update_frames);
+        set(&mut config.viz.graphs_len, &self.viz_graphs_len);
+    }
+}
+
+impl Cli {
+    /// Defaults, then the config file, then the flags
+    pub fn sim_config(&self) -> io::Result<SimConfig> {
+        let (config, sets_arch) = match &self.config_file {
+            Some(path) => (SimConfig::load(path)?, SimConfig::sets_arch(path)?),
+            None => (SimConfig::default(), false),
+        };
+        self.override_config(config, !sets_arch)
+    }
+
+    /// With `default_arch` the arch is resized to the sensors and actions unless
+    /// `--nn-arch` is passed, an arch that was set is only validated
+    pub fn override_config(
+        &self,
+        mut config: SimConfig,
+        default_arch: bool,
+    ) -> io::Result<SimConfig> {
+        self.config.apply(&mut config);
+
+        // Levels set the board size which some sensors depend on
+        config.game.load_levels()?;
+        if default_arch && self.config.nn_arch.is_none() {
+            config.nn.fit_arch(&config.game);
+        }
+        config.validate()?;
+
+        Ok(config)
+    }
+}
@@ -1,31 +1,455 @@
-// Game
-pub const GRID_SIZE: i32 = 15;
-
-// Sim
-pub const NUM_AGENTS: usize = 1000;
-pub const NUM_STEPS: usize = 75;
-pub const NUM_THREADS: usize = 8;
-
-// Pop
-pub const POP_RETAINED: f32 = 0.1;
-pub const POP_RETAINED_MUTATED: f32 = 0.0;
-pub const POP_ROULETTE: f32 = 0.6;
-pub const POP_TOURNAMENT: f32 = 0.1;
-pub const POP_NUM_RANDOM: f32 = 0.2;
-
-// Data
-pub const SAVE_FILE_NAME: &str = "data/net.json";
-pub const LOAD_FILE_NAME: &str = "data/net-100-2.json";
-pub const IS_LOAD_SAVED_DATA: bool = false;
-pub const IS_SAVE_BEST_NET: bool = false;
-
-// NN
-pub const NN_ARCH: [usize; 4] = [24, 16, 8, 4];
-
-// Viz
-pub const IS_LOW_DETAIL_MODE: bool = false;
-pub const USE_GAME_CANVAS: bool = false;
-pub const VIZ_GAME_SCALE: i32 = 3;
-pub const VIZ_OFFSET: i32 = 2;
-pub const VIZ_UPDATE_FRAMES: u32 = 50;
-pub const VIZ_GRAPHS_LEN: usize = 45;
+//! Configs
+//! Runtime settings for the simulation, defaults match the original constants
+//!
+//! Configs can be stored as toml or json files, each section is a table
+
+use std::{
+    fs::{self, create_dir_all},
+    io::{self, ErrorKind},
+    path::Path,
+    sync::Arc,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::agent::ActionSpace;
+use crate::curriculum::CurriculumStage;
+use crate::fitness::FitnessKind;
+use crate::level::Level;
+use crate::mutation::ScheduleKind;
+use crate::nn::{Activation, CrossoverKind, MutationKind, NetMeta};
+use crate::reproduction::StageConfig;
+use crate::sensors::SensorKind;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SimConfig {
+    pub game: GameConfig,
+    pub sim: RunConfig,
+    pub pop: PopConfig,
+    pub data: DataConfig,
+    pub nn: NNConfig,
+    pub viz: VizConfig,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GameConfig {
+    /// Walls are at x = 0 and x = width
+    pub width: i32,
+    /// Walls are at y = 0 and y = height
+    pub height: i32,
+    /// No walls, moving off one edge comes back in on the other
+    pub wrap: bool,
+    /// Level names or files, each game picks one. Replaces the board size when set
+    pub levels: Vec<String>,
+    /// Filled from `levels` by `load_levels`
+    #[serde(skip)]
+    pub maps: Vec<Arc<Level>>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RunConfig {
+    pub num_agents: usize,
+    pub num_steps: usize,
+    pub num_threads: usize,
+    /// Picked at random when not set, the run config records the one used
+    pub seed: Option<u64>,
+    /// Board sizes to train on in order, replaces the game board when set
+    pub curriculum: Vec<CurriculumStage>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PopConfig {
+    /// Reproduction stages, run in order to breed the next generation
+    pub pipeline: Vec<StageConfig>,
+    /// Used by the crossover stages
+    pub crossover: CrossoverKind,
+    pub mutation: MutationKind,
+    pub mutation_schedule: ScheduleKind,
+    pub fitness: FitnessKind,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DataConfig {
+    pub save_file_name: String,
+    pub load_file_name: String,
+    pub is_load_saved_data: bool,
+    pub is_save_best_net: bool,
+    pub checkpoint_file_name: String,
+    /// Generations between checkpoints, 0 disables checkpoints
+    pub checkpoint_every: usize,
+    /// Per generation metrics, `.csv` or json lines
+    pub metrics_file_name: Option<String>,
+    /// The best game of every generation is recorded here when set
+    pub replay_dir: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NNConfig {
+    pub arch: Vec<usize>,
+    /// One per layer after the input layer
+    pub activations: Vec<Activation>,
+    pub actions: ActionSpace,
+    /// Inputs relative to the snake heading instead of the board
+    pub egocentric: bool,
+    /// The input layer size is the sum of the sensor sizes
+    pub sensors: Vec<SensorKind>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VizConfig {
+    pub is_low_detail_mode: bool,
+    pub use_game_canvas: bool,
+    pub game_scale: i32,
+    pub offset: i32,
+    pub update_frames: u32,
+    pub graphs_len: usize,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        Self {
+            width: 15,
+            height: 15,
+            wrap: false,
+            levels: Vec::new(),
+            maps: Vec::new(),
+        }
+    }
+}
+
+impl Default for RunConfig {
+    fn default() -> Self {
+        Self {
+            num_agents: 1000,
+            num_steps: 75,
+            num_threads: 8,
+            seed: None,
+            curriculum: Vec::new(),
+        }
+    }
+}
+
+impl Default for PopConfig {
+    fn default() -> Self {
+        Self {
+            pipeline: StageConfig::default_pipeline(),
+            crossover: CrossoverKind::Uniform,
+            mutation: MutationKind::Uniform,
+            mutation_schedule: ScheduleKind::ScoreBanded,
+            fitness: FitnessKind::Classic,
+        }
+    }
+}
+
+impl Default for DataConfig {
+    fn default() -> Self {
+        Self {
+            save_file_name: "data/net.json".to_string(),
+            load_file_name: "data/net-100-2.json".to_string(),
+            is_load_saved_data: false,
+            is_save_best_net: false,
+            checkpoint_file_name: "data/checkpoint.json".to_string(),
+            checkpoint_every: 0,
+            metrics_file_name: None,
+            replay_dir: None,
+        }
+    }
+}
+
+impl Default for NNConfig {
+    fn default() -> Self {
+        Self {
+            arch: vec![24, 16, 8, 4],
+            activations: vec![Activation::Relu; 3],
+            actions: ActionSpace::Absolute,
+            egocentric: false,
+            sensors: SensorKind::default_suite(),
+        }
+    }
+}
+
+impl Default for VizConfig {
+    fn default() -> Self {
+        Self {
+            is_low_detail_mode: false,
+            use_game_canvas: false,
+            game_scale: 3,
+            offset: 2,
+            update_frames: 50,
+            graphs_len: 45,
+        }
+    }
+}
+
+impl GameConfig {
+    /// The largest level when levels are loaded
+    #[must_use]
+    pub fn max_score(&self) -> usize {
+        self.maps
+            .iter()
+            .map(|level| level.num_floor_cells())
+            .max()
+            .unwrap_or(((self.width - 1) * (self.height - 1)) as usize)
+    }
+
+    /// Reads the level files and takes the board size from them,
+    /// every level has to be the same size
+    pub fn load_levels(&mut self) -> io::Result<()> {
+        self.maps = self
+            .levels
+            .iter()
+            .map(|name| Level::load(name).map(Arc::new))
+            .collect::<io::Result<_>>()?;
+
+        let Some(first) = self.maps.first() else {
+            return Ok(());
+        };
+        let (width, height) = (first.width, first.height);
+        if let Some(level) = self
+            .maps
+            .iter()
+            .find(|level| (level.width, level.height) != (width, height))
+        {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "level {} is {}x{} but level {} is {width}x{height}, levels must be the same size",
+                    level.name, level.width, level.height, first.name
+                ),
+            ));
+        }
+
+        self.width = width;
+        self.height = height;
+        Ok(())
+    }
+}
+
+impl NNConfig {
+    /// Recorded in every net created for the run
+    #[must_use]
+    pub fn meta(&self) -> NetMeta {
+        NetMeta {
+            actions: self.actions,
+            egocentric: self.egocentric,
+            sensors: self.sensors.clone(),
+        }
+    }
+
+    #[must_use]
+    pub fn num_inputs(&self, game: &GameConfig) -> usize {
+        SensorKind::num_inputs(&self.sensors, game)
+    }
+
+    /// Sets the input and output layer sizes from the sensors and actions
+    pub fn fit_arch(&mut self, game: &GameConfig) {
+        let num_inputs = self.num_inputs(game);
+        let num_outputs = self.actions.num_outputs();
+        if let [first, .., last] = self.arch.as_mut_slice() {
+            *first = num_inputs;
+            *last = num_outputs;
+        }
+    }
+}
+
+impl SimConfig {
+    /// Json files are picked by extension, everything else is parsed as toml
+    pub fn load(path: &str) -> io::Result<Self> {
+        let buff = fs::read_to_string(path)?;
+        if Self::is_json(path) {
+            return Ok(serde_json::from_str(&buff)?);
+        }
+
+        toml::from_str(&buff).map_err(|err| io::Error::new(ErrorKind::InvalidData, err))
+    }
+
+    /// Whether the file sets `nn.arch` rather than leaving it to the defaults
+    pub fn sets_arch(path: &str) -> io::Result<bool> {
+        let buff = fs::read_to_string(path)?;
+        let has_arch = if Self::is_json(path) {
+            let value: serde_json::Value = serde_json::from_str(&buff)?;
+            value.get("nn").and_then(|nn| nn.get("arch")).is_some()
+        } else {
+            let value: toml::Table =
+                toml::from_str(&buff).map_err(|err| io::Error::new(ErrorKind::InvalidData, err))?;
+            value.get("nn").and_then(|nn| nn.get("arch")).is_some()
+        };
+
+        Ok(has_arch)
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let contents = if Self::is_json(path) {
+            serde_json::to_string_pretty(self)?
+        } else {
+            self.to_toml()?
+        };
+
+        let path = Path::new(path);
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        fs::write(path, contents)
+    }
+
+    pub fn to_toml(&self) -> io::Result<String> {
+        toml::to_string_pretty(self).map_err(|err| io::Error::new(ErrorKind::InvalidData, err))
+    }
+
+    /// Catch bad settings up front instead of panicking mid simulation
+    pub fn validate(&self) -> io::Result<()> {
+        let mut errors = Vec::new();
+
+        if self.game.width < 6 || self.game.height < 6 {
+            errors.push(format!(
+                "game.width and game.height must be at least 6, got {}x{}",
+                self.game.width, self.game.height
+            ));
+        }
+
+        if self.sim.num_agents == 0 {
+            errors.push("sim.num_agents must be greater than 0".to_string());
+        }
+        if self.sim.num_threads == 0 {
+            errors.push("sim.num_threads must be greater than 0".to_string());
+        }
+
+        let curriculum = &self.sim.curriculum;
+        errors.extend(
+            curriculum
+                .iter()
+                .enumerate()
+                .filter_map(|(idx, stage)| stage.validate(idx + 1 == curriculum.len()).err())
+                .map(|err| format!("sim.curriculum {err}")),
+        );
+        if !curriculum.is_empty() && self.nn.sensors.contains(&SensorKind::Grid) {
+            errors.push(
+                "nn.sensors grid needs a fixed board, it can't be used with sim.curriculum"
+                    .to_string(),
+            );
+        }
+
+        if !curriculum.is_empty() && !self.game.levels.is_empty() {
+            errors.push(
+                "game.levels set the board size, they can't be used with sim.curriculum"
+                    .to_string(),
+            );
+        }
+        if self.game.wrap && !self.game.levels.is_empty() {
+            errors.push(
+                "game.levels keep their border walls, they can't be used with game.wrap"
+                    .to_string(),
+            );
+        }
+
+        let pipeline = &self.pop.pipeline;
+        errors.extend(
+            pipeline
+                .iter()
+                .filter_map(|stage| stage.validate().err())
+                .map(|err| format!("pop.pipeline {err}")),
+        );
+        if let Err(err) = self.pop.crossover.validate() {
+            errors.push(format!("pop.crossover {err}"));
+        }
+        if let Err(err) = self.pop.mutation_schedule.validate() {
+            errors.push(format!("pop.mutation_schedule {err}"));
+        }
+        let total: f64 = pipeline.iter().map(|stage| stage.fraction).sum();
+        if (total - 1.0).abs() > 1e-3 {
+            errors.push(format!(
+                "pop.pipeline fractions must sum to 1.0, got {total}"
+            ));
+        }
+
+        let arch = &self.nn.arch;
+        if arch.len() < 2 || arch.contains(&0) {
+            errors.push(format!(
+                "nn.arch needs at least 2 non empty layers, got {arch:?}"
+            ));
+        } else {
+            let num_inputs = self.nn.num_inputs(&self.game);
+            if arch[0] != num_inputs {
+                errors.push(format!(
+                    "nn.arch[0] must match the {} sensor inputs, got {}",
+                    num_inputs, arch[0]
+                ));
+            }
+            let num_outputs = self.nn.actions.num_outputs();
+            if arch[arch.len() - 1] != num_outputs {
+                errors.push(format!(
+                    "nn.arch output layer must have {} nodes for {:?} actions, got {}",
+                    num_outputs,
+                    self.nn.actions,
+                    arch[arch.len() - 1]
+                ));
+            }
+        }
+
+        if self.nn.sensors.is_empty() {
+            errors.push("nn.sensors needs at least 1 sensor".to_string());
+        }
+        errors.extend(
+            self.nn
+                .sensors
+                .iter()
+                .filter_map(|sensor| sensor.validate().err())
+                .map(|err| format!("nn.sensors {err}")),
+        );
+
+        let activations = &self.nn.activations;
+        if activations.len() + 1 != arch.len() {
+            errors.push(format!(
+                "nn.activations needs one entry per layer after the input, got {} for {} layers",
+                activations.len(),
+                arch.len()
+            ));
+        }
+        if activations
+            .iter()
+            .rev()
+            .skip(1)
+            .any(|&a| a == Activation::Softmax)
+        {
+            errors.push("nn.activations can only use softmax for the output layer".to_string());
+        }
+
+        if self.viz.game_scale <= 0 {
+            errors.push("viz.game_scale must be greater than 0".to_string());
+        }
+        if self.viz.update_frames == 0 {
+            errors.push("viz.update_frames must be greater than 0".to_string());
+        }
+
+        if errors.is_empty() {
+            return Ok(());
+        }
+
+        Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid config:\n  {}", errors.join("\n  ")),
+        ))
+    }
+
+    /// Config file stored next to a network, eg. `data/net.json` -> `data/net.toml`
+    #[must_use]
+    pub fn path_for_net(net_path: &str) -> String {
+        Path::new(net_path)
+            .with_extension("toml")
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn is_json(path: &str) -> bool {
+        Path::new(path)
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("json"))
+    }
+}
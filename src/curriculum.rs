@@ -0,0 +1,278 @@
+//! Curriculum
+//! Trains on a sequence of board sizes, moving to the next one once a stage is done
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::SimConfig;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CurriculumStage {
+    pub width: i32,
+    pub height: i32,
+    /// Done once a generation reaches this max score
+    #[serde(default)]
+    pub until_score: Option<usize>,
+    /// Done after this many generations on the stage
+    #[serde(default)]
+    pub until_gens: Option<usize>,
+}
+
+/// Where a run is in its curriculum, stored in checkpoints
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CurriculumState {
+    pub stage: usize,
+    pub stage_start_gen: usize,
+}
+
+pub struct Curriculum {
+    stages: Vec<CurriculumStage>,
+    state: CurriculumState,
+}
+
+impl Curriculum {
+    #[must_use]
+    pub fn new(config: &SimConfig, state: CurriculumState) -> Self {
+        Self {
+            stages: config.sim.curriculum.clone(),
+            state,
+        }
+    }
+
+    #[must_use]
+    pub fn state(&self) -> CurriculumState {
+        self.state
+    }
+
+    /// `None` when the run has no curriculum
+    #[must_use]
+    pub fn stage(&self) -> Option<usize> {
+        (!self.stages.is_empty()).then_some(self.state.stage)
+    }
+
+    #[must_use]
+    pub fn num_stages(&self) -> usize {
+        self.stages.len()
+    }
+
+    /// The config with the board of the current stage
+    #[must_use]
+    pub fn stage_config(&self, config: &SimConfig) -> SimConfig {
+        let mut config = config.clone();
+        if let Some(stage) = self.stages.get(self.state.stage) {
+            config.game.width = stage.width;
+            config.game.height = stage.height;
+        }
+        config
+    }
+
+    /// Called once a generation is done, returns the next stage
+    /// when the current one is finished
+    pub fn advance(&mut self, gen_count: usize, gen_max_score: usize) -> Option<CurriculumStage> {
+        let stage = self.stages.get(self.state.stage)?;
+        let next = self.stages.get(self.state.stage + 1)?;
+
+        let gens_on_stage = gen_count + 1 - self.state.stage_start_gen;
+        let is_done = stage
+            .until_score
+            .is_some_and(|score| gen_max_score >= score)
+            || stage.until_gens.is_some_and(|gens| gens_on_stage >= gens);
+        if !is_done {
+            return None;
+        }
+
+        self.state = CurriculumState {
+            stage: self.state.stage + 1,
+            stage_start_gen: gen_count + 1,
+        };
+        Some(*next)
+    }
+}
+
+impl CurriculumStage {
+    pub fn validate(&self, is_last: bool) -> Result<(), String> {
+        if self.width < 6 || self.height < 6 {
+            return Err(format!("stage {self} board must be at least 6x6"));
+        }
+        if self.until_score == Some(0) || self.until_gens == Some(0) {
+            return Err(format!("stage {self} conditions must be greater than 0"));
+        }
+        if !is_last && self.until_score.is_none() && self.until_gens.is_none() {
+            return Err(format!(
+                "stage {self} needs a score or gens condition to move on"
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+// eg. 8x8:score(10), 10x10:gens(50), 12x12:score(30):gens(100), 15x15
+impl FromStr for CurriculumStage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        let board = parts.next().unwrap_or_default();
+        let (width, height) = board
+            .split_once('x')
+            .and_then(|(w, h)| Some((w.trim().parse().ok()?, h.trim().parse().ok()?)))
+            .ok_or_else(|| format!("expected a WIDTHxHEIGHT board, got {board:?}"))?;
+
+        let mut stage = Self {
+            width,
+            height,
+            until_score: None,
+            until_gens: None,
+        };
+        for cond in parts {
+            let (name, value) = cond
+                .split_once('(')
+                .and_then(|(name, rest)| Some((name.trim(), rest.strip_suffix(')')?)))
+                .ok_or_else(|| format!("expected score(N) or gens(N), got {cond:?}"))?;
+            let value = value
+                .trim()
+                .parse()
+                .map_err(|_| format!("bad curriculum condition in {s:?}"))?;
+            match name {
+                "score" => stage.until_score = Some(value),
+                "gens" => stage.until_gens = Some(value),
+                _ => return Err(format!("expected score(N) or gens(N), got {cond:?}")),
+            }
+        }
+
+        Ok(stage)
+    }
+}
+
+impl fmt::Display for CurriculumStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)?;
+        if let Some(score) = self.until_score {
+            write!(f, ":score({score})")?;
+        }
+        if let Some(gens) = self.until_gens {
+            write!(f, ":gens({gens})")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn curriculum(stages: &str) -> Curriculum {
+        let mut config = SimConfig::default();
+        config.sim.curriculum = stages
+            .split(',')
+            .map(|stage| stage.parse().unwrap())
+            .collect();
+        Curriculum::new(&config, CurriculumState::default())
+    }
+
+    #[test]
+    fn score_stage_ends_once_a_generation_reaches_it() {
+        let mut curriculum = curriculum("8x8:score(10),10x10");
+        assert_eq!(curriculum.advance(0, 9), None);
+        assert_eq!(curriculum.advance(1, 3), None);
+
+        let next = curriculum.advance(2, 10).unwrap();
+        assert_eq!((next.width, next.height), (10, 10));
+        assert_eq!(
+            curriculum.state(),
+            CurriculumState {
+                stage: 1,
+                stage_start_gen: 3
+            }
+        );
+
+        // The last stage never ends
+        assert_eq!(curriculum.advance(3, 1000), None);
+        assert_eq!(curriculum.stage(), Some(1));
+    }
+
+    #[test]
+    fn gens_stage_counts_from_its_first_generation() {
+        let mut curriculum = curriculum("8x8:gens(2),10x10:gens(3),12x12");
+        assert_eq!(curriculum.advance(0, 0), None);
+        assert!(curriculum.advance(1, 0).is_some());
+
+        // Generations 2, 3 and 4 are on the second stage
+        assert_eq!(curriculum.advance(2, 0), None);
+        assert_eq!(curriculum.advance(3, 0), None);
+        let next = curriculum.advance(4, 0).unwrap();
+        assert_eq!((next.width, next.height), (12, 12));
+    }
+
+    #[test]
+    fn either_condition_ends_a_stage() {
+        let mut by_score = curriculum("8x8:score(10):gens(50),10x10");
+        assert!(by_score.advance(0, 10).is_some());
+
+        let mut by_gens = curriculum("8x8:score(10):gens(2),10x10");
+        assert_eq!(by_gens.advance(0, 1), None);
+        assert!(by_gens.advance(1, 1).is_some());
+    }
+
+    #[test]
+    fn no_curriculum_never_advances() {
+        let mut curriculum = Curriculum::new(&SimConfig::default(), CurriculumState::default());
+        assert_eq!(curriculum.stage(), None);
+        assert_eq!(curriculum.advance(100, 1000), None);
+    }
+
+    #[test]
+    fn stages_round_trip_through_strings() {
+        for s in [
+            "8x8:score(10)",
+            "10x12:gens(50)",
+            "12x12:score(30):gens(100)",
+            "15x15",
+        ] {
+            let stage: CurriculumStage = s.parse().unwrap();
+            assert_eq!(stage.to_string(), s);
+        }
+
+        let stage: CurriculumStage = "12x9:gens(5):score(3)".parse().unwrap();
+        assert_eq!(
+            stage,
+            CurriculumStage {
+                width: 12,
+                height: 9,
+                until_score: Some(3),
+                until_gens: Some(5),
+            }
+        );
+    }
+
+    #[test]
+    fn bad_stage_specs_are_rejected() {
+        for s in [
+            "8",
+            "8x",
+            "axb",
+            "8x8:score",
+            "8x8:score(x)",
+            "8x8:steps(5)",
+            "8x8:score(5",
+        ] {
+            assert!(s.parse::<CurriculumStage>().is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn stages_are_validated() {
+        let stage = |s: &str| s.parse::<CurriculumStage>().unwrap();
+        assert!(stage("5x8:score(3)").validate(false).is_err());
+        assert!(stage("8x8:score(0)").validate(false).is_err());
+        assert!(stage("8x8:gens(0)").validate(true).is_err());
+
+        // Only the last stage can go on forever
+        assert!(stage("8x8").validate(false).is_err());
+        assert!(stage("8x8").validate(true).is_ok());
+        assert!(stage("8x8:gens(5)").validate(false).is_ok());
+    }
+}
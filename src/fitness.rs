@@ -0,0 +1,224 @@
+//! Fitness
+//! Scores a finished game, the population selects parents based on it
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::game::Game;
+
+pub trait FitnessFn: Send + Sync {
+    /// Must be positive, roulette selection uses it as a weight
+    fn fitness(&self, game: &Game) -> f32;
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FitnessKind {
+    #[default]
+    Classic,
+    Score,
+    StepPenalty,
+    FoodPerStep,
+    Survival,
+}
+
+/// The original formula, exponential in score early on and cubic later,
+/// scaled by the number of steps taken
+pub struct Classic;
+
+/// Only the snake length
+pub struct Score;
+
+/// Cubic in score, divided down by the average number of steps per food.
+/// Snakes that never eat get 0, whenever they die
+pub struct StepPenalty {
+    pub penalty: f32,
+}
+
+/// Food eaten per step, rewards direct paths to the food.
+/// Snakes that never eat get 0, whenever they die
+pub struct FoodPerStep;
+
+/// Steps survived, multiplied by the food eaten
+pub struct Survival;
+
+impl FitnessKind {
+    #[must_use]
+    pub fn build(self) -> Arc<dyn FitnessFn> {
+        match self {
+            Self::Classic => Arc::new(Classic),
+            Self::Score => Arc::new(Score),
+            Self::StepPenalty => Arc::new(StepPenalty::default()),
+            Self::FoodPerStep => Arc::new(FoodPerStep),
+            Self::Survival => Arc::new(Survival),
+        }
+    }
+}
+
+impl FitnessFn for Classic {
+    fn fitness(&self, game: &Game) -> f32 {
+        let score = game.score() as f32;
+        if score <= 1.0 {
+            return 1.0;
+        }
+
+        let mut fitness = 1.0;
+        if score < 5.0 {
+            fitness *= 2.0_f32.powf(score);
+            fitness *= score;
+            fitness *= game.total_steps as f32 * 0.1;
+        } else {
+            fitness *= score * score * score;
+            fitness *= game.total_steps as f32 * 0.1;
+        }
+
+        fitness
+    }
+}
+
+impl FitnessFn for Score {
+    fn fitness(&self, game: &Game) -> f32 {
+        game.score() as f32
+    }
+}
+
+impl Default for StepPenalty {
+    fn default() -> Self {
+        Self { penalty: 0.01 }
+    }
+}
+
+impl FitnessFn for StepPenalty {
+    fn fitness(&self, game: &Game) -> f32 {
+        if game.food_eaten() == 0 {
+            return 0.0;
+        }
+
+        let score = game.score() as f32;
+        let steps_per_food = game.total_steps as f32 / game.food_eaten() as f32;
+        score * score * score / (1.0 + self.penalty * steps_per_food)
+    }
+}
+
+impl FitnessFn for FoodPerStep {
+    fn fitness(&self, game: &Game) -> f32 {
+        game.food_eaten() as f32 / (game.total_steps + 1) as f32
+    }
+}
+
+impl FitnessFn for Survival {
+    fn fitness(&self, game: &Game) -> f32 {
+        (game.total_steps + 1) as f32 * (game.food_eaten() + 1) as f32
+    }
+}
+
+impl FromStr for FitnessKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "classic" => Ok(Self::Classic),
+            "score" => Ok(Self::Score),
+            "step_penalty" => Ok(Self::StepPenalty),
+            "food_per_step" => Ok(Self::FoodPerStep),
+            "survival" => Ok(Self::Survival),
+            _ => Err(format!("unknown fitness {s:?}, expected classic, score, step_penalty, food_per_step or survival")),
+        }
+    }
+}
+
+impl fmt::Display for FitnessKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Classic => write!(f, "classic"),
+            Self::Score => write!(f, "score"),
+            Self::StepPenalty => write!(f, "step_penalty"),
+            Self::FoodPerStep => write!(f, "food_per_step"),
+            Self::Survival => write!(f, "survival"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GameConfig;
+
+    const KINDS: [FitnessKind; 5] = [
+        FitnessKind::Classic,
+        FitnessKind::Score,
+        FitnessKind::StepPenalty,
+        FitnessKind::FoodPerStep,
+        FitnessKind::Survival,
+    ];
+
+    fn game(food_eaten: usize, total_steps: usize) -> Game {
+        let mut game = Game::new(&GameConfig::default(), 7);
+        let tail = game.body[game.body.len() - 1];
+        game.body.extend(vec![tail; food_eaten]);
+        game.total_steps = total_steps;
+        game
+    }
+
+    fn fitness(kind: FitnessKind, game: &Game) -> f32 {
+        let fitness = kind.build().fitness(game);
+        assert!(fitness.is_finite() && fitness >= 0.0, "{kind}: {fitness}");
+        fitness
+    }
+
+    #[test]
+    fn dying_early_never_beats_eating() {
+        let suicide = game(0, 1);
+        let eater = game(10, 200);
+        let slow_eater = game(1, 500);
+        for kind in KINDS {
+            assert!(fitness(kind, &eater) > fitness(kind, &suicide), "{kind}");
+            assert!(
+                fitness(kind, &slow_eater) >= fitness(kind, &suicide),
+                "{kind}"
+            );
+        }
+    }
+
+    #[test]
+    fn more_food_in_the_same_steps_ranks_higher() {
+        for kind in KINDS {
+            assert!(
+                fitness(kind, &game(12, 300)) > fitness(kind, &game(6, 300)),
+                "{kind}"
+            );
+        }
+    }
+
+    #[test]
+    fn step_penalty_and_food_per_step_prefer_direct_paths() {
+        for kind in [FitnessKind::StepPenalty, FitnessKind::FoodPerStep] {
+            assert!(fitness(kind, &game(5, 60)) > fitness(kind, &game(5, 400)));
+
+            // Without food the number of steps makes no difference
+            assert_eq!(fitness(kind, &game(0, 1)), fitness(kind, &game(0, 300)));
+        }
+    }
+
+    #[test]
+    fn survival_and_classic_reward_steps() {
+        for kind in [FitnessKind::Survival, FitnessKind::Classic] {
+            assert!(fitness(kind, &game(3, 300)) > fitness(kind, &game(3, 30)));
+        }
+        assert!(
+            fitness(FitnessKind::Survival, &game(0, 300))
+                > fitness(FitnessKind::Survival, &game(0, 1))
+        );
+    }
+
+    #[test]
+    fn kinds_round_trip_through_strings() {
+        for kind in KINDS {
+            assert_eq!(kind.to_string().parse::<FitnessKind>(), Ok(kind));
+        }
+        assert!("fastest".parse::<FitnessKind>().is_err());
+    }
+}
@@ -1,7 +1,60 @@
 //! Snake Game
 //! Handles all game related logic
 
-use crate::{FourDirs, Point, GRID_SIZE};
+use std::iter;
+use std::sync::Arc;
+
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::level::Level;
+use crate::{seeded_rng, FourDirs, GameConfig, Point, RngStream, SimRng};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GameOutcome {
+    #[default]
+    Running,
+    /// The snake fills every free cell
+    Won,
+    HitWall,
+    HitSelf,
+    /// Ran into another snake in an arena
+    HitSnake,
+    Starved,
+}
+
+/// How the games of a generation ended
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutcomeCounts {
+    pub won: usize,
+    pub hit_wall: usize,
+    pub hit_self: usize,
+    #[serde(default)]
+    pub hit_snake: usize,
+    pub starved: usize,
+}
+
+/// Everything a game did that depends on the rng or the player,
+/// enough to play it back exactly
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct GameRecord {
+    pub start_body: Vec<Point>,
+    pub start_dir: FourDirs,
+    /// The first food and every food spawned after it
+    pub foods: Vec<Point>,
+    pub moves: Vec<FourDirs>,
+}
+
+#[derive(Clone, Copy)]
+enum FoodSource {
+    /// The game rng
+    Random,
+    /// See `share_foods`
+    Shared { seed: u64, key: u64 },
+    /// See `set_external_food`
+    External,
+}
 
 #[derive(Clone)]
 pub struct Game {
@@ -11,32 +64,142 @@ pub struct Game {
     pub dir: FourDirs,
 
     pub is_dead: bool,
+    pub outcome: GameOutcome,
     pub total_steps: usize,
     pub no_food_steps: usize,
+
+    width: i32,
+    height: i32,
+    wrap: bool,
+    level: Option<Arc<Level>>,
+    record: GameRecord,
+    /// Recorded foods to spawn, in order
+    food_script: Vec<Point>,
+    /// Where the foods come from once the script runs out
+    food_source: FoodSource,
+    /// Cells of the other snakes in an arena, solid like the body
+    others: Vec<Point>,
+    rng: SimRng,
 }
 
 impl Default for Game {
     fn default() -> Self {
-        Self::new()
+        Self::new(&GameConfig::default(), rand::random())
+    }
+}
+
+impl OutcomeCounts {
+    pub fn add(&mut self, outcome: GameOutcome) {
+        match outcome {
+            GameOutcome::Running => {}
+            GameOutcome::Won => self.won += 1,
+            GameOutcome::HitWall => self.hit_wall += 1,
+            GameOutcome::HitSelf => self.hit_self += 1,
+            GameOutcome::HitSnake => self.hit_snake += 1,
+            GameOutcome::Starved => self.starved += 1,
+        }
+    }
+
+    #[must_use]
+    pub fn deaths(&self) -> usize {
+        self.hit_wall + self.hit_self + self.hit_snake + self.starved
+    }
+}
+
+impl FromIterator<GameOutcome> for OutcomeCounts {
+    fn from_iter<I: IntoIterator<Item = GameOutcome>>(iter: I) -> Self {
+        let mut counts = Self::default();
+        for outcome in iter {
+            counts.add(outcome);
+        }
+        counts
     }
 }
 
 impl Game {
+    /// Head plus two body segments
+    pub const START_LEN: usize = 3;
+
+    /// The seed drives the level, the starting direction and every food spawn
+    #[must_use]
+    pub fn new(config: &GameConfig, seed: u64) -> Self {
+        let mut rng = SimRng::seed_from_u64(seed);
+        let level = match config.maps.len() {
+            0 => None,
+            len => Some(config.maps[rng.gen_range(0..len)].clone()),
+        };
+        let (width, height) = (config.width, config.height);
+
+        // The body trails behind the heading so going straight is always safe
+        let (body, dir) = match &level {
+            Some(level) => (level.start_body(), level.start_dir()),
+            None => {
+                let dir = FourDirs::get_rand_dir(&mut rng);
+                let head = Point::new(width / 2, height / 2);
+                let (dx, dy) = dir.opposite().value();
+                let body = (0..Self::START_LEN as i32)
+                    .map(|i| Point::new(head.x + dx * i, head.y + dy * i))
+                    .collect();
+                (body, dir)
+            }
+        };
+        let food = Self::rand_free_cell(level.as_deref(), &body, width, height, &mut rng);
+
+        Self::with_start(config, level, body, dir, food, rng)
+    }
+
+    /// Starts from a recorded game on `level` and spawns its foods in order,
+    /// the moves are left to the caller. Nothing is drawn from the rng, which
+    /// only places foods once the recorded ones run out
     #[must_use]
-    pub fn new() -> Self {
-        let head = Point::new(GRID_SIZE / 2, GRID_SIZE / 2);
-        let mut body = vec![head];
-        body.push(Point::new(head.x - 1, head.y));
-        body.push(Point::new(head.x - 2, head.y));
+    pub fn from_record(
+        config: &GameConfig,
+        level: Option<Arc<Level>>,
+        record: &GameRecord,
+    ) -> Self {
+        let mut game = Self::with_start(
+            config,
+            level,
+            record.start_body.clone(),
+            record.start_dir,
+            record.foods[0],
+            SimRng::seed_from_u64(0),
+        );
+        game.food_script = record.foods[1..].to_vec();
+        game
+    }
 
+    fn with_start(
+        config: &GameConfig,
+        level: Option<Arc<Level>>,
+        body: Vec<Point>,
+        dir: FourDirs,
+        food: Point,
+        rng: SimRng,
+    ) -> Self {
         Self {
+            head: body[0],
+            record: GameRecord {
+                start_body: body.clone(),
+                start_dir: dir,
+                foods: vec![food],
+                moves: Vec::new(),
+            },
+            food_script: Vec::new(),
+            food_source: FoodSource::Random,
+            others: Vec::new(),
             body,
-            head,
-            food: Point::rand(),
-            dir: FourDirs::get_rand_dir(),
+            food,
+            dir,
             is_dead: false,
+            outcome: GameOutcome::Running,
             total_steps: 0,
             no_food_steps: 0,
+            width: config.width,
+            height: config.height,
+            wrap: config.wrap,
+            level,
+            rng,
         }
     }
 
@@ -47,22 +210,118 @@ impl Game {
 
         self.total_steps += 1;
         self.dir = dir;
-        self.handle_food_collision();
+        self.record.moves.push(dir);
+        let has_eaten = self.handle_food_collision();
         self.update_snake_positions();
 
-        if self.is_wall(self.head) || self.is_snake_body(self.head) {
-            self.is_dead = true;
+        if self.is_wall(self.head) {
+            self.end(GameOutcome::HitWall);
+        } else if self.is_snake_body(self.head) {
+            self.end(GameOutcome::HitSelf);
+        } else if has_eaten {
+            self.place_food();
         }
     }
 
+    /// The n-th food spawn is drawn from its own rng derived from `seed`, `key`
+    /// and n, so games sharing them get the same foods in the same order.
+    /// A food that lands on the snake is drawn again from the same rng
+    pub fn share_foods(&mut self, seed: u64, key: u64) {
+        self.food_source = FoodSource::Shared { seed, key };
+    }
+
+    /// Foods are placed by the caller through `food`, eg. an arena sharing one
+    /// food between its snakes. The game neither spawns nor records them
+    pub fn set_external_food(&mut self) {
+        self.food_source = FoodSource::External;
+    }
+
+    /// Cells taken by the other snakes of an arena, set before every move.
+    /// They are solid to the sensors, running into them is up to the arena
+    pub fn set_others(&mut self, cells: Vec<Point>) {
+        self.others = cells;
+    }
+
+    pub fn end(&mut self, outcome: GameOutcome) {
+        self.is_dead = true;
+        self.outcome = outcome;
+    }
+
     #[must_use]
     pub fn score(&self) -> usize {
         self.body.len()
     }
 
+    /// Number of cells inside the walls
+    #[must_use]
+    pub fn max_score(&self) -> usize {
+        match &self.level {
+            Some(level) => level.num_floor_cells(),
+            None => ((self.width - 1) * (self.height - 1)) as usize,
+        }
+    }
+
+    #[must_use]
+    pub fn food_eaten(&self) -> usize {
+        self.score().saturating_sub(Self::START_LEN)
+    }
+
+    /// The border is never a wall on wrap around boards, level walls always are
     #[must_use]
     pub fn is_wall(&self, pt: Point) -> bool {
-        pt.x >= GRID_SIZE || pt.x <= 0 || pt.y >= GRID_SIZE || pt.y <= 0
+        let is_border = pt.x >= self.width || pt.x <= 0 || pt.y >= self.height || pt.y <= 0;
+        (!self.wrap && is_border) || self.level.as_ref().is_some_and(|l| l.is_wall(pt))
+    }
+
+    #[must_use]
+    pub fn level(&self) -> Option<&Level> {
+        self.level.as_deref()
+    }
+
+    #[must_use]
+    pub fn record(&self) -> &GameRecord {
+        &self.record
+    }
+
+    #[must_use]
+    pub fn is_wrap(&self) -> bool {
+        self.wrap
+    }
+
+    /// The cell one step from `pt`, wrapped back inside on wrap around boards
+    #[must_use]
+    pub fn step(&self, pt: Point, dir: (i32, i32)) -> Point {
+        let next = Point::new(pt.x + dir.0, pt.y + dir.1);
+        if !self.wrap {
+            return next;
+        }
+
+        // Cells inside the border go from 1 to width - 1
+        Point::new(
+            (next.x - 1).rem_euclid(self.width - 1) + 1,
+            (next.y - 1).rem_euclid(self.height - 1) + 1,
+        )
+    }
+
+    #[must_use]
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+
+    #[must_use]
+    pub fn height(&self) -> i32 {
+        self.height
+    }
+
+    #[must_use]
+    pub fn others(&self) -> &[Point] {
+        &self.others
+    }
+
+    /// The body or another snake, what the sensors see as solid
+    #[must_use]
+    pub fn is_snake(&self, pt: Point) -> bool {
+        self.is_snake_body(pt) || self.others.contains(&pt)
     }
 
     #[must_use]
@@ -71,9 +330,23 @@ impl Game {
         self.body[1..].contains(&pt)
     }
 
+    /// Direction the tail moves in, the head direction for a one cell snake
+    #[must_use]
+    pub fn tail_dir(&self) -> FourDirs {
+        let [.., body, tail] = self.body.as_slice() else {
+            return self.dir;
+        };
+
+        match (body.x - tail.x, body.y - tail.y) {
+            (-1, 0) => FourDirs::Left,
+            (1, 0) => FourDirs::Right,
+            (0, 1) => FourDirs::Bottom,
+            _ => FourDirs::Top,
+        }
+    }
+
     fn update_snake_positions(&mut self) {
-        self.head.x += self.dir.value().0;
-        self.head.y += self.dir.value().1;
+        self.head = self.step(self.head, self.dir.value());
 
         for i in (1..self.body.len()).rev() {
             self.body[i] = self.body[i - 1];
@@ -81,14 +354,179 @@ impl Game {
         self.body[0] = self.head;
     }
 
-    fn handle_food_collision(&mut self) {
+    /// Grows the snake when the head is on the food
+    fn handle_food_collision(&mut self) -> bool {
         if !self.head.equals(self.food) {
             self.no_food_steps += 1;
-            return;
+            return false;
         }
 
         self.no_food_steps = 0;
         self.body.push(Point::new(self.head.x, self.head.y));
-        self.food = Point::rand();
+        true
+    }
+
+    // Called once the snake has moved so the food never lands on it
+    fn place_food(&mut self) {
+        if let FoodSource::External = self.food_source {
+            return;
+        }
+        if self.body.len() >= self.max_score() {
+            self.end(GameOutcome::Won);
+            return;
+        }
+
+        let spawned = self.record.foods.len() - 1;
+        self.food = match (self.food_script.get(spawned), self.food_source) {
+            (Some(&food), _) => food,
+            (None, FoodSource::Random) => Self::rand_free_cell(
+                self.level.as_deref(),
+                &self.body,
+                self.width,
+                self.height,
+                &mut self.rng,
+            ),
+            (None, FoodSource::External) => unreachable!("external foods are placed by the caller"),
+            (None, FoodSource::Shared { seed, key }) => {
+                let mut rng = seeded_rng(seed, RngStream::Versus, &[key, spawned as u64]);
+                iter::repeat_with(|| {
+                    Point::new(rng.gen_range(1..self.width), rng.gen_range(1..self.height))
+                })
+                .find(|&pt| !self.is_wall(pt) && !self.body.contains(&pt))
+                .unwrap()
+            }
+        };
+        self.record.foods.push(self.food);
+    }
+
+    // Uniform over the cells inside the border that aren't a level wall or the snake,
+    // callers make sure there is one
+    fn rand_free_cell(
+        level: Option<&Level>,
+        body: &[Point],
+        width: i32,
+        height: i32,
+        rng: &mut SimRng,
+    ) -> Point {
+        let free_cells: Vec<Point> = (1..height)
+            .flat_map(|y| (1..width).map(move |x| Point::new(x, y)))
+            .filter(|pt| !level.is_some_and(|l| l.is_wall(*pt)) && !body.contains(pt))
+            .collect();
+        free_cells[rng.gen_range(0..free_cells.len())]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Heading right along row `y` with the food straight ahead
+    fn game_on_row(y: i32, script: &[Point]) -> Game {
+        let mut foods = vec![Point::new(4, y)];
+        foods.extend(script);
+        Game::from_record(
+            &GameConfig::default(),
+            None,
+            &GameRecord {
+                start_body: (1..=3).rev().map(|x| Point::new(x, y)).collect(),
+                start_dir: FourDirs::Right,
+                foods,
+                moves: Vec::new(),
+            },
+        )
+    }
+
+    // The snake grows the step after its head reaches the food
+    fn eat(game: &mut Game) {
+        let score = game.score();
+        while game.score() == score {
+            game.update(FourDirs::Right);
+        }
+        assert!(!game.is_dead);
+    }
+
+    #[test]
+    fn games_start_with_the_body_behind_the_heading() {
+        let config = GameConfig::default();
+        let mut dirs = Vec::new();
+        for seed in 0..100 {
+            let mut game = Game::new(&config, seed);
+            let (dx, dy) = game.dir.value();
+            assert_eq!(game.body[1], Point::new(game.head.x - dx, game.head.y - dy));
+            dirs.push(game.dir);
+
+            game.update(game.dir);
+            assert!(!game.is_dead);
+        }
+        assert!(dirs.contains(&FourDirs::Left) && dirs.contains(&FourDirs::Top));
+    }
+
+    #[test]
+    fn nth_spawn_reads_the_nth_scripted_food() {
+        let script = [Point::new(6, 3), Point::new(8, 3), Point::new(2, 9)];
+        let mut game = game_on_row(3, &script);
+
+        eat(&mut game);
+        assert_eq!(game.food, script[0]);
+        eat(&mut game);
+        assert_eq!(game.food, script[1]);
+        assert_eq!(
+            game.record().foods,
+            [Point::new(4, 3), script[0], script[1]]
+        );
+    }
+
+    #[test]
+    fn shared_foods_match_unless_on_the_snake() {
+        let mut a = game_on_row(3, &[]);
+        let mut b = game_on_row(6, &[]);
+        a.share_foods(7, 2);
+        b.share_foods(7, 2);
+
+        // More spawns than a fixed length script would hold
+        let num_spawns = a.max_score() * 5;
+        for _ in 0..num_spawns {
+            a.place_food();
+            b.place_food();
+        }
+
+        let (a_foods, b_foods) = (&a.record().foods, &b.record().foods);
+        assert_eq!(a_foods.len(), num_spawns + 1);
+        let mut num_same = 0;
+        for (&a_food, &b_food) in a_foods.iter().zip(b_foods).skip(1) {
+            assert!(!a.is_wall(a_food) && !a.body.contains(&a_food));
+            assert!(!b.is_wall(b_food) && !b.body.contains(&b_food));
+            if a_food == b_food {
+                num_same += 1;
+            } else {
+                // The shared draw was under one of the snakes and redrawn
+                assert!(b.body.contains(&a_food) || a.body.contains(&b_food));
+            }
+        }
+        assert!(num_same > num_spawns * 9 / 10);
+    }
+
+    #[test]
+    fn shared_foods_depend_on_the_key() {
+        let mut a = game_on_row(3, &[]);
+        let mut b = game_on_row(3, &[]);
+        a.share_foods(7, 0);
+        b.share_foods(7, 1);
+        for _ in 0..20 {
+            a.place_food();
+            b.place_food();
+        }
+        assert_ne!(a.record().foods, b.record().foods);
+    }
+
+    #[test]
+    fn random_food_once_the_script_runs_out() {
+        let script = [Point::new(6, 3)];
+        let mut game = game_on_row(3, &script);
+
+        eat(&mut game);
+        eat(&mut game);
+        assert_eq!(game.record().foods.len(), 3);
+        assert!(!game.is_wall(game.food) && !game.body.contains(&game.food));
     }
 }
@@ -0,0 +1,261 @@
+//! Levels
+//! Obstacle maps loaded from plain text files under `data/levels`
+//!
+//! A level file is an ascii map, `#` for walls and `.` for floor. Line `y` of the
+//! map is row `y` of the board and the outer ring is the border, so a 15x15 board
+//! is 16 lines of 16 cells. Lines starting with `;` are comments and an optional
+//! `spawn x y dir` line before the map fixes where the snake starts,
+//! eg. `spawn 3 3 right`. Without it the snake starts in the middle of the board
+//! heading right
+
+use std::{
+    fmt, fs,
+    io::{self, ErrorKind},
+    path::Path,
+};
+
+use crate::game::Game;
+use crate::{FourDirs, Point};
+
+#[derive(Clone, Debug)]
+pub struct Level {
+    pub name: String,
+    pub width: i32,
+    pub height: i32,
+    /// Head position and direction, the body trails behind the head
+    pub spawn: Option<(Point, FourDirs)>,
+    walls: Vec<bool>,
+}
+
+impl Level {
+    pub const DIR: &'static str = "data/levels";
+
+    /// Names are looked up in `data/levels`, anything with a path separator
+    /// or an extension is read as a file path
+    pub fn load(name: &str) -> io::Result<Self> {
+        let path = if name.contains(['/', '\\', '.']) {
+            Path::new(name).to_path_buf()
+        } else {
+            Path::new(Self::DIR).join(format!("{name}.txt"))
+        };
+        let contents = fs::read_to_string(&path).map_err(|err| {
+            io::Error::new(err.kind(), format!("level {}: {err}", path.display()))
+        })?;
+        let name = path
+            .file_stem()
+            .map_or(name.to_string(), |s| s.to_string_lossy().into_owned());
+
+        Self::parse(&name, &contents).map_err(|err| io::Error::new(ErrorKind::InvalidData, err))
+    }
+
+    pub fn parse(name: &str, contents: &str) -> Result<Self, String> {
+        let mut spawn = None;
+        let mut rows: Vec<&str> = Vec::new();
+        for line in contents.lines().map(str::trim_end) {
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+            if let Some(rest) = line.strip_prefix("spawn") {
+                spawn = Some(parse_spawn(rest).map_err(|err| format!("level {name}: {err}"))?);
+                continue;
+            }
+            rows.push(line);
+        }
+
+        let height = rows.len() as i32 - 1;
+        let width = rows.first().map_or(0, |row| row.chars().count()) as i32 - 1;
+        if width < 6 || height < 6 {
+            return Err(format!(
+                "level {name}: the board must be at least 6x6 inside the border, got {width}x{height}"
+            ));
+        }
+
+        let mut walls = Vec::with_capacity(((width + 1) * (height + 1)) as usize);
+        for (y, row) in rows.iter().enumerate() {
+            if row.chars().count() as i32 != width + 1 {
+                return Err(format!(
+                    "level {name}: row {y} has {} cells, expected {}",
+                    row.chars().count(),
+                    width + 1
+                ));
+            }
+            for (x, cell) in row.chars().enumerate() {
+                walls.push(match cell {
+                    '#' => true,
+                    '.' => false,
+                    _ => {
+                        return Err(format!(
+                            "level {name}: unexpected {cell:?} at {x},{y}, expected '#' or '.'"
+                        ))
+                    }
+                });
+            }
+        }
+
+        let level = Self {
+            name: name.to_string(),
+            width,
+            height,
+            spawn,
+            walls,
+        };
+        if level.start_body().iter().any(|&pt| level.is_blocked(pt)) {
+            return Err(format!(
+                "level {name}: the snake must start on floor cells, add or move the spawn line"
+            ));
+        }
+
+        Ok(level)
+    }
+
+    /// Map walls only, the border is handled by the game
+    #[must_use]
+    pub fn is_wall(&self, pt: Point) -> bool {
+        if pt.x < 0 || pt.y < 0 || pt.x > self.width || pt.y > self.height {
+            return false;
+        }
+        self.walls[(pt.y * (self.width + 1) + pt.x) as usize]
+    }
+
+    /// Floor cells inside the border
+    #[must_use]
+    pub fn num_floor_cells(&self) -> usize {
+        (1..self.height)
+            .flat_map(|y| (1..self.width).map(move |x| Point::new(x, y)))
+            .filter(|&pt| !self.is_wall(pt))
+            .count()
+    }
+
+    /// Head first, trailing behind `start_dir`. The default start is the middle of the board
+    #[must_use]
+    pub fn start_body(&self) -> Vec<Point> {
+        let head = self
+            .spawn
+            .map_or(Point::new(self.width / 2, self.height / 2), |(head, _)| {
+                head
+            });
+        let behind = self.start_dir().opposite().value();
+        (0..Game::START_LEN as i32)
+            .map(|i| Point::new(head.x + behind.0 * i, head.y + behind.1 * i))
+            .collect()
+    }
+
+    #[must_use]
+    pub fn start_dir(&self) -> FourDirs {
+        self.spawn.map_or(FourDirs::Right, |(_, dir)| dir)
+    }
+
+    fn is_blocked(&self, pt: Point) -> bool {
+        pt.x <= 0 || pt.y <= 0 || pt.x >= self.width || pt.y >= self.height || self.is_wall(pt)
+    }
+}
+
+// Same format as the level files
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some((head, dir)) = self.spawn {
+            let dir = match dir {
+                FourDirs::Left => "left",
+                FourDirs::Right => "right",
+                FourDirs::Top => "top",
+                FourDirs::Bottom => "bottom",
+            };
+            writeln!(f, "spawn {} {} {dir}", head.x, head.y)?;
+        }
+        for row in self.walls.chunks((self.width + 1) as usize) {
+            let row: String = row.iter().map(|&w| if w { '#' } else { '.' }).collect();
+            writeln!(f, "{row}")?;
+        }
+        Ok(())
+    }
+}
+
+// eg. spawn 3 3 right
+fn parse_spawn(s: &str) -> Result<(Point, FourDirs), String> {
+    let parts: Vec<&str> = s.split_whitespace().collect();
+    let [x, y, dir] = parts.as_slice() else {
+        return Err(format!("expected spawn x y dir, got {:?}", s.trim()));
+    };
+    let coord = |v: &str| {
+        v.parse::<i32>()
+            .map_err(|_| format!("bad spawn coordinate {v:?}"))
+    };
+    let dir = match *dir {
+        "left" => FourDirs::Left,
+        "right" => FourDirs::Right,
+        "top" => FourDirs::Top,
+        "bottom" => FourDirs::Bottom,
+        _ => {
+            return Err(format!(
+                "unknown spawn dir {dir:?}, expected left, right, top or bottom"
+            ))
+        }
+    };
+
+    Ok((Point::new(coord(x)?, coord(y)?), dir))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAP: &str = "\
+; a wall in the middle
+spawn 3 2 right
+########
+#......#
+#......#
+#..##..#
+#..##..#
+#......#
+#......#
+########
+";
+
+    #[test]
+    fn parses_walls_and_spawn() {
+        let level = Level::parse("test", MAP).unwrap();
+        assert_eq!((level.width, level.height), (7, 7));
+        assert_eq!(level.spawn, Some((Point::new(3, 2), FourDirs::Right)));
+        assert!(level.is_wall(Point::new(3, 3)) && level.is_wall(Point::new(4, 4)));
+        assert!(!level.is_wall(Point::new(2, 2)));
+        assert_eq!(level.num_floor_cells(), 6 * 6 - 4);
+    }
+
+    #[test]
+    fn display_round_trips() {
+        let level = Level::parse("test", MAP).unwrap();
+        let reparsed = Level::parse("test", &level.to_string()).unwrap();
+        assert_eq!(reparsed.to_string(), level.to_string());
+        assert_eq!(reparsed.spawn, level.spawn);
+        assert_eq!(reparsed.walls, level.walls);
+    }
+
+    #[test]
+    fn rejects_rows_of_different_widths() {
+        let map = MAP.replace(
+            "#..##..#\n#......#\n#......#",
+            "#..##..#\n#.....#\n#......#",
+        );
+        let err = Level::parse("test", &map).unwrap_err();
+        assert!(err.contains("row 5 has 7 cells, expected 8"), "{err}");
+    }
+
+    #[test]
+    fn rejects_unknown_cells() {
+        let map = MAP.replacen("#......#", "#...x..#", 1);
+        let err = Level::parse("test", &map).unwrap_err();
+        assert!(err.contains("unexpected 'x' at 4,1"), "{err}");
+    }
+
+    #[test]
+    fn rejects_a_spawn_on_a_wall() {
+        let map = MAP.replace("spawn 3 2 right", "spawn 3 3 left");
+        let err = Level::parse("test", &map).unwrap_err();
+        assert!(err.contains("must start on floor cells"), "{err}");
+
+        // The body trails behind the head into the border
+        let map = MAP.replace("spawn 3 2 right", "spawn 2 2 right");
+        assert!(Level::parse("test", &map).is_err());
+    }
+}
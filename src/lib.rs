@@ -1,8 +1,20 @@
 pub mod agent;
+pub mod arena;
+pub mod checkpoint;
+pub mod cli;
 pub mod configs;
+pub mod curriculum;
+pub mod fitness;
 pub mod game;
+pub mod level;
+pub mod metrics;
+pub mod mutation;
 pub mod nn;
 pub mod pop;
+pub mod replay;
+pub mod reproduction;
+pub mod selection;
+pub mod sensors;
 pub mod sim;
 pub mod utils;
 pub mod viz;
@@ -1,35 +1,614 @@
+use std::cmp::Ordering;
+use std::path::Path;
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::Arc;
 use std::time::Duration;
 use std::{io, time::Instant};
 
-use crossterm::event::{self, Event, KeyCode};
+use clap::Parser;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use rand::Rng;
+use rayon::prelude::*;
 
 use sim::Simulation;
-use snake_tui::{sim, NUM_THREADS};
+use snake_tui::agent::Agent;
+use snake_tui::arena::Arena;
+use snake_tui::checkpoint::Checkpoint;
+use snake_tui::cli::{
+    ArenaArgs, Cli, Command, ConfigFileArgs, EvalArgs, NetArgs, PlayArgs, ReplayArgs, ResumeArgs,
+    TrainArgs, VersusArgs,
+};
+use snake_tui::game::{Game, GameOutcome, OutcomeCounts};
+use snake_tui::nn::Net;
+use snake_tui::replay::{Playback, Replay, Seek};
+use snake_tui::viz::{ArenaViz, PlayViz, ReplayViz, VersusTally, Viz};
+use snake_tui::{seeded_rng, sim, FourDirs, RngStream, SimConfig};
 
 fn main() -> io::Result<()> {
+    let cli = Cli::parse();
+
+    // A resumed run uses the config stored in its checkpoint
+    if let Command::Resume(args) = &cli.command {
+        return resume(&cli, args);
+    }
+
+    let config = cli.sim_config()?;
+    init_thread_pool(&config);
+
+    match &cli.command {
+        Command::Train(args) => train(&config, args),
+        Command::Watch(args) => watch(&config, args),
+        Command::Eval(args) => eval(&config, args),
+        Command::Config(args) => write_config(&config, args),
+        Command::Replay(args) => replay(&config, args),
+        Command::Play(args) => play(&config, args),
+        Command::Versus(args) => versus(&config, args),
+        Command::Arena(args) => arena(&config, args),
+        Command::Resume(_) => unreachable!(),
+    }
+}
+
+fn init_thread_pool(config: &SimConfig) {
     rayon::ThreadPoolBuilder::new()
-        .num_threads(NUM_THREADS)
+        .num_threads(config.sim.num_threads)
         .build_global()
         .unwrap();
+}
+
+fn train(config: &SimConfig, args: &TrainArgs) -> io::Result<()> {
+    let sim = Simulation::new(config, args.headless)?;
+    run_sim(sim, args)
+}
+
+fn resume(cli: &Cli, args: &ResumeArgs) -> io::Result<()> {
+    let defaults = cli.sim_config()?;
+    let path = args
+        .checkpoint
+        .as_ref()
+        .unwrap_or(&defaults.data.checkpoint_file_name);
+
+    let mut checkpoint = Checkpoint::load(path)?;
+    checkpoint.config = cli.override_config(checkpoint.config, false)?;
+    init_thread_pool(&checkpoint.config);
+
+    let sim = Simulation::resume(checkpoint, args.train.headless)?;
+    run_sim(sim, &args.train)
+}
+
+fn run_sim(mut sim: Simulation, args: &TrainArgs) -> io::Result<()> {
+    let is_done = |sim: &Simulation| args.max_gens.is_some_and(|max| sim.gen_count() >= max);
+
+    if args.headless {
+        // Ctrl-C stops like a finished run, so the last checkpoint is written
+        let is_interrupted = Arc::new(AtomicBool::new(false));
+        let flag = Arc::clone(&is_interrupted);
+        ctrlc::set_handler(move || flag.store(true, atomic::Ordering::Relaxed))
+            .map_err(io::Error::other)?;
+
+        let res = loop {
+            if is_done(&sim) || is_interrupted.load(atomic::Ordering::Relaxed) {
+                break Ok(());
+            }
+            if let Err(err) = sim.update() {
+                break Err(err);
+            }
+        };
+        sim.stop()?;
+        return res;
+    }
 
-    let mut sim = Simulation::new()?;
     let mut last_poll = Instant::now();
+    let res = loop {
+        if is_done(&sim) {
+            break Ok(());
+        }
 
-    loop {
         if last_poll.elapsed() > Duration::from_millis(15) {
-            if event::poll(Duration::ZERO)? {
-                last_poll = Instant::now();
-                if let Event::Key(key) = event::read()? {
-                    if let KeyCode::Esc | KeyCode::Char('q') = key.code {
-                        break;
+            match poll_key() {
+                Ok(Some(key)) if is_quit_key(&key) => break Ok(()),
+                Ok(Some(key)) => {
+                    if let Some(viz) = sim.viz_mut() {
+                        control_viz(viz, key.code);
                     }
                 }
+                Ok(None) => {}
+                Err(err) => break Err(err),
             }
+            last_poll = Instant::now();
             sim.draw();
         }
 
-        sim.update();
+        if let Err(err) = sim.update() {
+            break Err(err);
+        }
+    };
+
+    sim.stop()?;
+    res
+}
+
+fn watch(config: &SimConfig, args: &NetArgs) -> io::Result<()> {
+    let net = load_net(config, args)?;
+    let mut viz = Viz::new(config)?;
+    viz.update_brain(net);
+
+    let res = loop {
+        match poll_key() {
+            Ok(Some(key)) if is_quit_key(&key) => break Ok(()),
+            Ok(Some(key)) => control_viz(&mut viz, key.code),
+            Ok(None) => {}
+            Err(err) => break Err(err),
+        }
+
+        // Viz only steps the agent once every few updates
+        viz.update();
+        viz.draw();
+        std::thread::sleep(Duration::from_millis(1));
+    };
+
+    Viz::restore_terminal()?;
+    res
+}
+
+fn eval(config: &SimConfig, args: &EvalArgs) -> io::Result<()> {
+    let net = load_net(config, &args.net)?;
+    let seed = config.sim.seed.unwrap_or_else(rand::random);
+    let games: Vec<(Option<String>, Game)> = (0..args.games)
+        .into_par_iter()
+        .map(|idx| {
+            let mut agent = Agent::with_brain(net.clone(), config, eval_game_seed(seed, idx));
+            while agent.update() {}
+            let level = agent.game.level().map(|level| level.name.clone());
+            (level, agent.game)
+        })
+        .collect();
+
+    let all: Vec<&Game> = games.iter().map(|(_, game)| game).collect();
+    println!(
+        "Seed: {}, {}",
+        seed,
+        game_stats(&all, config.game.max_score())
+    );
+
+    // Held out levels are easier to compare one by one
+    if config.game.maps.len() > 1 {
+        for level in &config.game.maps {
+            let played: Vec<&Game> = games
+                .iter()
+                .filter(|(name, _)| name.as_ref() == Some(&level.name))
+                .map(|(_, game)| game)
+                .collect();
+            println!(
+                "Level: {}, {}",
+                level.name,
+                game_stats(&played, level.num_floor_cells())
+            );
+        }
+    }
+
+    Ok(())
+}
+
+// Game `idx` of a seed is the same board for eval and play
+fn eval_game_seed(seed: u64, idx: usize) -> u64 {
+    seeded_rng(seed, RngStream::Eval, &[idx as u64]).gen()
+}
+
+fn game_stats(games: &[&Game], max_score: usize) -> String {
+    let scores: Vec<usize> = games.iter().map(|game| game.score()).collect();
+    let wins = games
+        .iter()
+        .filter(|game| game.outcome == GameOutcome::Won)
+        .count();
+    let total: usize = scores.iter().sum();
+    format!(
+        "Games: {}, Wins: {}, Mean: {:.2}/{}, Min: {}/{}, Max: {}/{}",
+        scores.len(),
+        wins,
+        total as f32 / scores.len().max(1) as f32,
+        max_score,
+        scores.iter().min().unwrap_or(&0),
+        max_score,
+        scores.iter().max().unwrap_or(&0),
+        max_score,
+    )
+}
+
+fn replay(config: &SimConfig, args: &ReplayArgs) -> io::Result<()> {
+    const SPEEDS: [u32; 6] = [1, 2, 5, 10, 20, 50];
+
+    let mut playback = Playback::new(Replay::load(&args.file)?)?;
+    let mut viz = ReplayViz::new(&config.viz)?;
+    let mut is_paused = false;
+    let mut speed_idx = 3;
+    let mut last_step = Instant::now();
+
+    let res = loop {
+        let key = match poll_key() {
+            Ok(key) => key,
+            Err(err) => break Err(err),
+        };
+        match key.map(|key| key.code) {
+            _ if key.as_ref().is_some_and(is_quit_key) => break Ok(()),
+            Some(KeyCode::Char(' ')) => is_paused = !is_paused,
+            Some(KeyCode::Left) => {
+                is_paused = true;
+                playback.seek(Seek::Back);
+            }
+            Some(KeyCode::Right) => {
+                is_paused = true;
+                playback.seek(Seek::Forward);
+            }
+            Some(KeyCode::Home) => playback.seek(Seek::Start),
+            Some(KeyCode::End) => playback.seek(Seek::End),
+            Some(KeyCode::Char('+' | '=')) => speed_idx = (speed_idx + 1).min(SPEEDS.len() - 1),
+            Some(KeyCode::Char('-')) => speed_idx = speed_idx.saturating_sub(1),
+            Some(KeyCode::Char('r')) => playback.restart(),
+            _ => {}
+        }
+
+        let step_time = Duration::from_secs(1) / SPEEDS[speed_idx];
+        if !is_paused && last_step.elapsed() >= step_time {
+            playback.step_forward();
+            last_step = Instant::now();
+        }
+
+        viz.draw(&playback, is_paused, SPEEDS[speed_idx]);
+        std::thread::sleep(Duration::from_millis(5));
+    };
+
+    Viz::restore_terminal()?;
+    res
+}
+
+fn play(config: &SimConfig, args: &PlayArgs) -> io::Result<()> {
+    let seed = config.sim.seed.unwrap_or_else(rand::random);
+    let tick = Duration::from_millis(args.tick_ms);
+    let mut viz = PlayViz::new(&config.viz, tick)?;
+    let screen_dirs = viz.screen_dirs();
+
+    let mut played: Vec<Game> = Vec::new();
+    let mut abandoned = 0;
+    let mut game = Game::new(&config.game, eval_game_seed(seed, 0));
+    let mut next_dir: Option<FourDirs> = None;
+    let mut last_tick = Instant::now();
+
+    let res = loop {
+        let key = match poll_key() {
+            Ok(key) => key,
+            Err(err) => break Err(err),
+        };
+        let dir = match key.map(|key| key.code) {
+            _ if key.as_ref().is_some_and(is_quit_key) => break Ok(()),
+            Some(KeyCode::Char('r' | 'R')) => {
+                // A game given up still counts, so a board can't be retried
+                if is_started(&game) {
+                    abandoned += usize::from(!game.is_dead);
+                    played.push(game);
+                }
+                game = Game::new(&config.game, eval_game_seed(seed, played.len()));
+                next_dir = None;
+                None
+            }
+            Some(code) => key_dir(code, screen_dirs),
+            None => None,
+        };
+        if let Some(dir) = dir.filter(|&dir| !is_turning_back(&game, dir)) {
+            next_dir = Some(dir);
+        }
+
+        if let Some(dir) = next_dir.filter(|_| !game.is_dead) {
+            if last_tick.elapsed() >= tick {
+                update_human(&mut game, dir, config);
+                last_tick = Instant::now();
+
+                if game.is_dead {
+                    if let Some(dir) = &config.data.replay_dir {
+                        let path = format!("{dir}/play-{}.json", played.len());
+                        if let Err(err) = Replay::from_game(&game, None).save(&path) {
+                            break Err(err);
+                        }
+                    }
+                }
+            }
+        }
+
+        let best_score = played.iter().map(Game::score).max().unwrap_or(0);
+        viz.draw(&game, played.len(), best_score.max(game.score()));
+        std::thread::sleep(Duration::from_millis(5));
+    };
+
+    Viz::restore_terminal()?;
+    if is_started(&game) {
+        abandoned += usize::from(!game.is_dead);
+        played.push(game);
+    }
+    if !played.is_empty() {
+        let played: Vec<&Game> = played.iter().collect();
+        println!(
+            "Seed: {}, {}, Abandoned: {}",
+            seed,
+            game_stats(&played, config.game.max_score()),
+            abandoned
+        );
+    }
+
+    res
+}
+
+fn versus(config: &SimConfig, args: &VersusArgs) -> io::Result<()> {
+    let net = load_net(config, &args.net)?;
+    let seed = config.sim.seed.unwrap_or_else(rand::random);
+    let tick = Duration::from_millis(args.play.tick_ms);
+    let mut viz = PlayViz::new(&config.viz, tick)?;
+    let screen_dirs = viz.screen_dirs();
+
+    // Both snakes start from the same seed and eat the same foods
+    let new_round = |idx: usize| {
+        let game_seed = eval_game_seed(seed, idx);
+        let mut human = Game::new(&config.game, game_seed);
+        let mut agent = Agent::with_brain(net.clone(), config, game_seed);
+        human.share_foods(seed, idx as u64);
+        agent.game.share_foods(seed, idx as u64);
+        (human, agent)
+    };
+
+    let mut played: Vec<(Game, Game)> = Vec::new();
+    let mut abandoned = 0;
+    let mut tally = VersusTally::default();
+    let (mut human, mut agent) = new_round(0);
+    let mut next_dir: Option<FourDirs> = None;
+    let mut last_tick = Instant::now();
+
+    let res = loop {
+        let key = match poll_key() {
+            Ok(key) => key,
+            Err(err) => break Err(err),
+        };
+        let dir = match key.map(|key| key.code) {
+            _ if key.as_ref().is_some_and(is_quit_key) => break Ok(()),
+            Some(KeyCode::Char('r' | 'R')) => {
+                // An unfinished round is given up and doesn't count, the next
+                // one is on a new board so a board can't be retried
+                let is_over = human.is_dead && agent.game.is_dead;
+                if is_started(&human) && !is_over {
+                    abandoned += 1;
+                }
+                if is_started(&human) {
+                    (human, agent) = new_round(played.len() + abandoned);
+                }
+                next_dir = None;
+                None
+            }
+            Some(code) => key_dir(code, screen_dirs),
+            None => None,
+        };
+        if let Some(dir) = dir.filter(|&dir| !is_turning_back(&human, dir)) {
+            next_dir = Some(dir);
+        }
+
+        // The net moves on the same ticks as the human, once the human has started
+        let is_over = human.is_dead && agent.game.is_dead;
+        if let Some(dir) = next_dir.filter(|_| !is_over) {
+            if last_tick.elapsed() >= tick {
+                update_human(&mut human, dir, config);
+                agent.update();
+                last_tick = Instant::now();
+
+                if human.is_dead && agent.game.is_dead {
+                    match human.score().cmp(&agent.game.score()) {
+                        Ordering::Greater => tally.human += 1,
+                        Ordering::Less => tally.net += 1,
+                        Ordering::Equal => tally.draws += 1,
+                    }
+                    played.push((human.clone(), agent.game.clone()));
+                }
+            }
+        }
+
+        let game_idx = if is_over {
+            played.len() + abandoned - 1
+        } else {
+            played.len() + abandoned
+        };
+        viz.draw_versus(&human, &agent.game, game_idx, tally);
+        std::thread::sleep(Duration::from_millis(5));
+    };
+
+    Viz::restore_terminal()?;
+    if !played.is_empty() || abandoned > 0 {
+        let max_score = config.game.max_score();
+        let humans: Vec<&Game> = played.iter().map(|(human, _)| human).collect();
+        let nets: Vec<&Game> = played.iter().map(|(_, net)| net).collect();
+        println!("Seed: {seed}");
+        println!("You: {}", game_stats(&humans, max_score));
+        println!("Net: {}", game_stats(&nets, max_score));
+        println!(
+            "Wins - You: {}, Net: {}, Draws: {}, Abandoned: {}",
+            tally.human, tally.net, tally.draws, abandoned
+        );
+    }
+
+    res
+}
+
+fn arena(config: &SimConfig, args: &ArenaArgs) -> io::Result<()> {
+    let nets = args
+        .nets
+        .iter()
+        .map(|path| Net::load_for(path, &config.game))
+        .collect::<io::Result<Vec<Net>>>()?;
+    let seed = config.sim.seed.unwrap_or_else(rand::random);
+    if args.headless {
+        return arena_stats(config, args, &nets, seed);
+    }
+
+    let names: Vec<String> = args
+        .nets
+        .iter()
+        .map(|path| {
+            Path::new(path)
+                .file_stem()
+                .map_or(path.clone(), |s| s.to_string_lossy().into_owned())
+        })
+        .collect();
+    let tick = Duration::from_millis(args.tick_ms);
+    let mut arena = Arena::new(&nets, config, eval_game_seed(seed, 0))?;
+    let mut viz = ArenaViz::new()?;
+    let mut wins = vec![0; nets.len()];
+    let mut round = 0;
+    let mut is_paused = false;
+    let mut last_tick = Instant::now();
+
+    let res = loop {
+        let key = match poll_key() {
+            Ok(key) => key,
+            Err(err) => break Err(err),
+        };
+        match key.map(|key| key.code) {
+            _ if key.as_ref().is_some_and(is_quit_key) => break Ok(()),
+            Some(KeyCode::Char(' ')) => is_paused = !is_paused,
+            _ => {}
+        }
+
+        // The last frame of a round stays up for a moment
+        let wait = if arena.is_over() {
+            Duration::from_secs(1)
+        } else {
+            tick
+        };
+        if !is_paused && last_tick.elapsed() >= wait {
+            if arena.is_over() {
+                round += 1;
+                arena = match Arena::new(&nets, config, eval_game_seed(seed, round)) {
+                    Ok(arena) => arena,
+                    Err(err) => break Err(err),
+                };
+            } else if arena.update() && arena.is_over() {
+                if let Some(winner) = arena.winner() {
+                    wins[winner] += 1;
+                }
+            }
+            last_tick = Instant::now();
+        }
+
+        viz.draw(&arena, &names, &wins, round);
+        std::thread::sleep(Duration::from_millis(5));
+    };
+
+    Viz::restore_terminal()?;
+    res
+}
+
+fn arena_stats(config: &SimConfig, args: &ArenaArgs, nets: &[Net], seed: u64) -> io::Result<()> {
+    let rounds = (0..args.games)
+        .into_par_iter()
+        .map(|idx| {
+            let mut arena = Arena::new(nets, config, eval_game_seed(seed, idx))?;
+            while arena.update() {}
+            Ok(arena)
+        })
+        .collect::<io::Result<Vec<Arena>>>()?;
+
+    let winners: Vec<Option<usize>> = rounds.iter().map(Arena::winner).collect();
+    println!(
+        "Seed: {}, Rounds: {}, Ties: {}",
+        seed,
+        rounds.len(),
+        winners.iter().filter(|winner| winner.is_none()).count()
+    );
+    for (i, path) in args.nets.iter().enumerate() {
+        let games: Vec<&Game> = rounds.iter().map(|arena| &arena.snakes[i].game).collect();
+        let outcomes: OutcomeCounts = games.iter().map(|game| game.outcome).collect();
+        println!(
+            "Snake {} ({}): Wins: {}, Mean: {:.2}, Max: {}, Wall: {}, Self: {}, Snake: {}, Starved: {}",
+            i + 1,
+            path,
+            winners.iter().filter(|&&winner| winner == Some(i)).count(),
+            games.iter().map(|game| game.score()).sum::<usize>() as f32
+                / games.len().max(1) as f32,
+            games.iter().map(|game| game.score()).max().unwrap_or(0),
+            outcomes.hit_wall,
+            outcomes.hit_self,
+            outcomes.hit_snake,
+            outcomes.starved,
+        );
+    }
+
+    Ok(())
+}
+
+// Arrows and WASD, `screen_dirs` are up, down, left and right on screen
+fn key_dir(code: KeyCode, screen_dirs: [FourDirs; 4]) -> Option<FourDirs> {
+    let [up, down, left, right] = screen_dirs;
+    match code {
+        KeyCode::Up | KeyCode::Char('w' | 'W') => Some(up),
+        KeyCode::Down | KeyCode::Char('s' | 'S') => Some(down),
+        KeyCode::Left | KeyCode::Char('a' | 'A') => Some(left),
+        KeyCode::Right | KeyCode::Char('d' | 'D') => Some(right),
+        _ => None,
+    }
+}
+
+// Turning back into the neck is ignored instead of ending the game
+fn is_turning_back(game: &Game, dir: FourDirs) -> bool {
+    game.step(game.head, dir.value()) == game.body[1]
+}
+
+fn write_config(config: &SimConfig, args: &ConfigFileArgs) -> io::Result<()> {
+    match &args.out {
+        Some(path) => config.save(path),
+        None => {
+            print!("{}", config.to_toml()?);
+            Ok(())
+        }
+    }
+}
+
+fn load_net(config: &SimConfig, args: &NetArgs) -> io::Result<Net> {
+    let path = args.net.as_ref().unwrap_or(&config.data.load_file_name);
+    Net::load_for(path, &config.game)
+}
+
+// Humans starve after as many steps without food as the nets do in eval
+fn update_human(game: &mut Game, dir: FourDirs, config: &SimConfig) {
+    game.update(dir);
+    let step_limit = Agent::step_limit(game.score(), config.sim.num_steps);
+    if !game.is_dead && game.no_food_steps >= step_limit {
+        game.end(GameOutcome::Starved);
+    }
+}
+
+fn is_started(game: &Game) -> bool {
+    !game.record().moves.is_empty()
+}
+
+// Pausing and stepping through the watched game
+fn control_viz(viz: &mut Viz, code: KeyCode) {
+    match code {
+        KeyCode::Char(' ') => viz.toggle_pause(),
+        KeyCode::Left => viz.seek(Seek::Back),
+        KeyCode::Right => viz.seek(Seek::Forward),
+        KeyCode::Home => viz.seek(Seek::Start),
+        KeyCode::End => viz.seek(Seek::End),
+        _ => {}
+    }
+}
+
+fn is_quit_key(key: &KeyEvent) -> bool {
+    let is_ctrl_c = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+    is_ctrl_c || matches!(key.code, KeyCode::Esc | KeyCode::Char('q'))
+}
+
+// Key presses only, without waiting
+fn poll_key() -> io::Result<Option<KeyEvent>> {
+    if event::poll(Duration::ZERO)? {
+        if let Event::Key(key) = event::read()? {
+            if key.kind != KeyEventKind::Release {
+                return Ok(Some(key));
+            }
+        }
     }
 
-    sim.stop()
+    Ok(None)
 }
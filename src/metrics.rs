@@ -0,0 +1,325 @@
+//! Metrics
+//! Per generation stats appended to a csv or json lines file
+
+use std::{
+    fs::{self, create_dir_all, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::Path,
+};
+
+use serde::Serialize;
+
+use crate::agent::Agent;
+use crate::sim::GenerationSummary;
+
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct GenerationMetrics {
+    pub gen_count: usize,
+    pub duration_secs: f32,
+    pub max_score: usize,
+    pub mean_score: f64,
+    pub median_score: f64,
+    pub sim_max_score: usize,
+    pub fitness_p10: f32,
+    pub fitness_p25: f32,
+    pub fitness_p50: f32,
+    pub fitness_p75: f32,
+    pub fitness_p90: f32,
+    pub fitness_max: f32,
+    pub mutation_rate: f64,
+    pub mutation_magnitude: f64,
+    pub deaths_wall: usize,
+    pub deaths_self: usize,
+    pub deaths_starved: usize,
+    pub wins: usize,
+    pub board_width: i32,
+    pub board_height: i32,
+    pub curriculum_stage: Option<usize>,
+}
+
+pub struct MetricsLog {
+    file: File,
+    format: MetricsFormat,
+}
+
+#[derive(Clone, Copy)]
+enum MetricsFormat {
+    Csv,
+    JsonLines,
+}
+
+const CSV_HEADER: &str = "gen_count,duration_secs,max_score,mean_score,median_score,\
+sim_max_score,fitness_p10,fitness_p25,fitness_p50,fitness_p75,fitness_p90,fitness_max,\
+mutation_rate,mutation_magnitude,deaths_wall,deaths_self,deaths_starved,wins,\
+board_width,board_height,curriculum_stage";
+
+impl GenerationMetrics {
+    #[must_use]
+    pub fn new(
+        summary: &GenerationSummary,
+        agents: &[Agent],
+        fitness: &[f32],
+        mutation_rate: f64,
+        mutation_magnitude: f64,
+    ) -> Self {
+        let mut scores: Vec<f64> = agents.iter().map(|a| a.game.score() as f64).collect();
+        let mut fitness = fitness.to_vec();
+        scores.sort_by(f64::total_cmp);
+        fitness.sort_by(f32::total_cmp);
+
+        Self {
+            gen_count: summary.gen_count,
+            duration_secs: summary.time_elapsed_secs,
+            max_score: summary.gen_max_score,
+            mean_score: scores.iter().sum::<f64>() / scores.len().max(1) as f64,
+            median_score: percentile(&scores, 0.5),
+            sim_max_score: summary.sim_max_score,
+            fitness_p10: percentile(&fitness, 0.1),
+            fitness_p25: percentile(&fitness, 0.25),
+            fitness_p50: percentile(&fitness, 0.5),
+            fitness_p75: percentile(&fitness, 0.75),
+            fitness_p90: percentile(&fitness, 0.9),
+            fitness_max: fitness.last().copied().unwrap_or_default(),
+            mutation_rate,
+            mutation_magnitude,
+            deaths_wall: summary.outcomes.hit_wall,
+            deaths_self: summary.outcomes.hit_self,
+            deaths_starved: summary.outcomes.starved,
+            wins: summary.outcomes.won,
+            board_width: summary.board_width,
+            board_height: summary.board_height,
+            curriculum_stage: summary.curriculum_stage,
+        }
+    }
+
+    fn to_csv_row(self) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            self.gen_count,
+            self.duration_secs,
+            self.max_score,
+            self.mean_score,
+            self.median_score,
+            self.sim_max_score,
+            self.fitness_p10,
+            self.fitness_p25,
+            self.fitness_p50,
+            self.fitness_p75,
+            self.fitness_p90,
+            self.fitness_max,
+            self.mutation_rate,
+            self.mutation_magnitude,
+            self.deaths_wall,
+            self.deaths_self,
+            self.deaths_starved,
+            self.wins,
+            self.board_width,
+            self.board_height,
+            self.curriculum_stage
+                .map_or(String::new(), |s| s.to_string()),
+        )
+    }
+}
+
+impl MetricsLog {
+    /// `.csv` files are written as csv, everything else as json lines.
+    /// Rows are appended so resumed runs keep their full history, a csv
+    /// written with other columns is refused rather than mixed into
+    pub fn open(path: &str) -> io::Result<Self> {
+        let path = Path::new(path);
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+
+        let format = MetricsFormat::for_path(path);
+        if let MetricsFormat::Csv = format {
+            check_csv_header(path)?;
+        }
+
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        if let MetricsFormat::Csv = format {
+            if file.metadata()?.len() == 0 {
+                writeln!(file, "{CSV_HEADER}")?;
+            }
+        }
+
+        Ok(Self { file, format })
+    }
+
+    /// Drops the rows from `gen_count` on before opening, a run resumed from a
+    /// checkpoint of that generation logs them again. Rewritten through a temp
+    /// file like checkpoints
+    pub fn resume(path: &str, gen_count: usize) -> io::Result<Self> {
+        let file_path = Path::new(path);
+        if file_path.exists() {
+            if let MetricsFormat::Csv = MetricsFormat::for_path(file_path) {
+                check_csv_header(file_path)?;
+            }
+
+            let kept: String = fs::read_to_string(file_path)?
+                .lines()
+                .filter(|line| row_gen_count(line).is_none_or(|gen| gen < gen_count))
+                .map(|line| format!("{line}\n"))
+                .collect();
+            let tmp_path = file_path.with_extension("tmp");
+            fs::write(&tmp_path, kept)?;
+            fs::rename(tmp_path, file_path)?;
+        }
+
+        Self::open(path)
+    }
+
+    pub fn write(&mut self, metrics: &GenerationMetrics) -> io::Result<()> {
+        let line = match self.format {
+            MetricsFormat::Csv => metrics.to_csv_row(),
+            MetricsFormat::JsonLines => serde_json::to_string(metrics)?,
+        };
+        writeln!(self.file, "{line}")
+    }
+}
+
+impl MetricsFormat {
+    fn for_path(path: &Path) -> Self {
+        if path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("csv"))
+        {
+            Self::Csv
+        } else {
+            Self::JsonLines
+        }
+    }
+}
+
+// An empty or missing file is fine, it gets the header on open
+fn check_csv_header(path: &Path) -> io::Result<()> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+    let header = match BufReader::new(file).lines().next() {
+        Some(line) => line?,
+        None => return Ok(()),
+    };
+
+    if header.trim_end() == CSV_HEADER {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} has different csv columns than this version writes, \
+                use a new metrics file",
+                path.display()
+            ),
+        ))
+    }
+}
+
+// `None` for the csv header
+fn row_gen_count(line: &str) -> Option<usize> {
+    if line.starts_with('{') {
+        let row: serde_json::Value = serde_json::from_str(line).ok()?;
+        row.get("gen_count")?.as_u64().map(|gen| gen as usize)
+    } else {
+        line.split(',').next()?.parse().ok()
+    }
+}
+
+// Nearest rank on already sorted values
+fn percentile<T: Copy + Default>(sorted: &[T], p: f64) -> T {
+    if sorted.is_empty() {
+        return T::default();
+    }
+
+    let idx = (p * (sorted.len() - 1) as f64).round() as usize;
+    sorted[idx]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_log(name: &str, gens: usize) -> String {
+        let path =
+            std::env::temp_dir().join(format!("snake-metrics-{}-{name}", std::process::id()));
+        let path = path.to_string_lossy().into_owned();
+        let _ = fs::remove_file(&path);
+        let mut log = MetricsLog::open(&path).unwrap();
+        for gen_count in 0..gens {
+            log.write(&GenerationMetrics {
+                gen_count,
+                ..Default::default()
+            })
+            .unwrap();
+        }
+        path
+    }
+
+    fn logged_gens(path: &str) -> Vec<usize> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .filter_map(row_gen_count)
+            .collect()
+    }
+
+    #[test]
+    fn resume_drops_rows_logged_after_the_checkpoint() {
+        for name in ["log.csv", "log.jsonl"] {
+            let path = write_log(name, 8);
+            let mut log = MetricsLog::resume(&path, 5).unwrap();
+            for gen_count in 5..7 {
+                log.write(&GenerationMetrics {
+                    gen_count,
+                    ..Default::default()
+                })
+                .unwrap();
+            }
+
+            assert_eq!(logged_gens(&path), (0..7).collect::<Vec<_>>());
+            if name.ends_with(".csv") {
+                assert!(fs::read_to_string(&path).unwrap().starts_with(CSV_HEADER));
+            }
+            fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn csv_with_other_columns_is_refused() {
+        let path = write_log("old.csv", 0);
+        fs::write(&path, "gen_count,max_score\n0,3\n").unwrap();
+
+        let err = MetricsLog::open(&path).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = MetricsLog::resume(&path, 1).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "gen_count,max_score\n0,3\n"
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn csv_with_the_same_columns_is_appended_to() {
+        let path = write_log("same.csv", 3);
+        let mut log = MetricsLog::open(&path).unwrap();
+        log.write(&GenerationMetrics {
+            gen_count: 3,
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(logged_gens(&path), [0, 1, 2, 3]);
+        assert_eq!(
+            fs::read_to_string(&path)
+                .unwrap()
+                .matches(CSV_HEADER)
+                .count(),
+            1
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,420 @@
+//! Mutation
+//! Schedules that pick the mutation rate and magnitude for each generation
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MutationParams {
+    /// Chance of each weight and bias being mutated
+    pub rate: f64,
+    /// Spread of the noise added to a mutated value
+    pub magnitude: f64,
+}
+
+/// What a schedule knows about the generation that just finished
+pub struct ScheduleInput {
+    pub gen_count: usize,
+    pub gen_max_score: usize,
+    /// Highest score possible on the board
+    pub max_score: usize,
+    /// Generations since the best score last improved
+    pub stagnant_gens: usize,
+}
+
+pub trait MutationSchedule: Send + Sync {
+    fn params(&self, input: &ScheduleInput) -> MutationParams;
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ScheduleKind {
+    Constant {
+        rate: f64,
+        magnitude: f64,
+    },
+    Decay {
+        rate: f64,
+        magnitude: f64,
+        decay: f64,
+        floor: f64,
+    },
+    #[default]
+    ScoreBanded,
+    Stagnation {
+        patience: usize,
+        boost: f64,
+    },
+}
+
+/// Same params every generation
+pub struct Constant {
+    pub params: MutationParams,
+}
+
+/// Rate and magnitude shrink by `decay` every generation,
+/// down to `floor` times the starting values
+pub struct Decay {
+    pub start: MutationParams,
+    pub decay: f64,
+    pub floor: f64,
+}
+
+/// The original schedule, smaller steps once the snakes fill more of the board
+pub struct ScoreBanded;
+
+/// Score banded, boosted by `boost` after `patience` generations
+/// without a new best score
+pub struct StagnationBoost {
+    pub patience: usize,
+    pub boost: f64,
+}
+
+impl ScheduleKind {
+    #[must_use]
+    pub fn build(self) -> Box<dyn MutationSchedule> {
+        match self {
+            Self::Constant { rate, magnitude } => Box::new(Constant {
+                params: MutationParams { rate, magnitude },
+            }),
+            Self::Decay {
+                rate,
+                magnitude,
+                decay,
+                floor,
+            } => Box::new(Decay {
+                start: MutationParams { rate, magnitude },
+                decay,
+                floor,
+            }),
+            Self::ScoreBanded => Box::new(ScoreBanded),
+            Self::Stagnation { patience, boost } => Box::new(StagnationBoost { patience, boost }),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let check_params = |rate: f64, magnitude: f64| {
+            if !(rate > 0.0 && rate <= 1.0) {
+                return Err(format!(
+                    "rate must be greater than 0.0 and at most 1.0, got {rate}"
+                ));
+            }
+            if !(magnitude > 0.0 && magnitude.is_finite()) {
+                return Err(format!("magnitude must be positive, got {magnitude}"));
+            }
+            Ok(())
+        };
+
+        match *self {
+            Self::Constant { rate, magnitude } => check_params(rate, magnitude),
+            Self::Decay {
+                rate,
+                magnitude,
+                decay,
+                floor,
+            } => {
+                check_params(rate, magnitude)?;
+                if !(decay > 0.0 && decay <= 1.0) {
+                    return Err(format!(
+                        "decay must be greater than 0.0 and at most 1.0, got {decay}"
+                    ));
+                }
+                if !(floor > 0.0 && floor <= 1.0) {
+                    return Err(format!(
+                        "floor must be greater than 0.0 and at most 1.0, got {floor}"
+                    ));
+                }
+                Ok(())
+            }
+            Self::ScoreBanded => Ok(()),
+            Self::Stagnation { patience, boost } => {
+                if patience == 0 {
+                    return Err("stagnation patience must be greater than 0".to_string());
+                }
+                if !(boost > 0.0 && boost.is_finite()) {
+                    return Err(format!("stagnation boost must be positive, got {boost}"));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl MutationSchedule for Constant {
+    fn params(&self, _input: &ScheduleInput) -> MutationParams {
+        self.params
+    }
+}
+
+impl MutationSchedule for Decay {
+    fn params(&self, input: &ScheduleInput) -> MutationParams {
+        let scale = self
+            .decay
+            .powi(input.gen_count.min(i32::MAX as usize) as i32)
+            .max(self.floor);
+        MutationParams {
+            rate: self.start.rate * scale,
+            magnitude: self.start.magnitude * scale,
+        }
+    }
+}
+
+impl MutationSchedule for ScoreBanded {
+    fn params(&self, input: &ScheduleInput) -> MutationParams {
+        let gen_max = input.gen_max_score as f64;
+        let max_score = input.max_score as f64;
+        let (magnitude, rate) = if gen_max > 0.75 * max_score {
+            (0.1, 0.15)
+        } else if gen_max > 0.5 * max_score {
+            (0.1, 0.25)
+        } else {
+            (0.5, 0.15)
+        };
+
+        MutationParams { rate, magnitude }
+    }
+}
+
+impl MutationSchedule for StagnationBoost {
+    fn params(&self, input: &ScheduleInput) -> MutationParams {
+        let params = ScoreBanded.params(input);
+        if input.stagnant_gens < self.patience {
+            return params;
+        }
+
+        MutationParams {
+            rate: (params.rate * self.boost).min(1.0),
+            magnitude: params.magnitude * self.boost,
+        }
+    }
+}
+
+// eg. constant(0.1,0.2), decay(0.15,0.5,0.99,0.1), score_banded, stagnation(20,2)
+impl FromStr for ScheduleKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad_params = || format!("bad schedule params in {s:?}");
+        let (name, params) = match s.trim().split_once('(') {
+            Some((name, rest)) => (name, Some(rest.strip_suffix(')').ok_or_else(bad_params)?)),
+            None => (s, None),
+        };
+        let params = params
+            .map(|p| {
+                p.split(',')
+                    .map(|v| v.trim().parse::<f64>())
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .transpose()
+            .map_err(|_| bad_params())?
+            .unwrap_or_default();
+        // Missing trailing params fall back to the defaults
+        let param = |idx: usize, default: f64| params.get(idx).copied().unwrap_or(default);
+
+        match name.trim() {
+            "constant" => Ok(Self::Constant {
+                rate: param(0, 0.15),
+                magnitude: param(1, 0.5),
+            }),
+            "decay" => Ok(Self::Decay {
+                rate: param(0, 0.15),
+                magnitude: param(1, 0.5),
+                decay: param(2, 0.99),
+                floor: param(3, 0.1),
+            }),
+            "score_banded" => Ok(Self::ScoreBanded),
+            "stagnation" => Ok(Self::Stagnation {
+                patience: param(0, 20.0) as usize,
+                boost: param(1, 2.0),
+            }),
+            _ => Err(format!(
+                "unknown schedule {name:?}, expected constant(rate,magnitude), \
+                decay(rate,magnitude,decay,floor), score_banded or stagnation(patience,boost)"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ScheduleKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Constant { rate, magnitude } => write!(f, "constant({rate},{magnitude})"),
+            Self::Decay {
+                rate,
+                magnitude,
+                decay,
+                floor,
+            } => write!(f, "decay({rate},{magnitude},{decay},{floor})"),
+            Self::ScoreBanded => write!(f, "score_banded"),
+            Self::Stagnation { patience, boost } => write!(f, "stagnation({patience},{boost})"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::nn::{Activation, MutationKind, Net};
+    use crate::SimRng;
+
+    fn input(gen_count: usize, stagnant_gens: usize) -> ScheduleInput {
+        ScheduleInput {
+            gen_count,
+            gen_max_score: 0,
+            max_score: 100,
+            stagnant_gens,
+        }
+    }
+
+    #[test]
+    fn decay_shrinks_down_to_the_floor() {
+        let schedule = ScheduleKind::Decay {
+            rate: 0.2,
+            magnitude: 0.5,
+            decay: 0.5,
+            floor: 0.1,
+        }
+        .build();
+
+        let first = schedule.params(&input(1, 0));
+        assert!((first.rate - 0.1).abs() < 1e-12);
+        assert!((first.magnitude - 0.25).abs() < 1e-12);
+
+        for gen_count in [4, 100, usize::MAX] {
+            let params = schedule.params(&input(gen_count, 0));
+            assert!((params.rate - 0.02).abs() < 1e-12, "{params:?}");
+            assert!((params.magnitude - 0.05).abs() < 1e-12, "{params:?}");
+        }
+    }
+
+    #[test]
+    fn stagnation_boosts_only_after_patience() {
+        let schedule = ScheduleKind::Stagnation {
+            patience: 5,
+            boost: 2.0,
+        }
+        .build();
+        let banded = ScoreBanded.params(&input(0, 0));
+
+        assert_eq!(schedule.params(&input(10, 4)), banded);
+        let boosted = schedule.params(&input(10, 5));
+        assert_eq!(boosted.rate, banded.rate * 2.0);
+        assert_eq!(boosted.magnitude, banded.magnitude * 2.0);
+        // A new best score resets the stagnant count and the boost with it
+        assert_eq!(schedule.params(&input(11, 0)), banded);
+    }
+
+    #[test]
+    fn stagnation_boost_caps_the_rate() {
+        let schedule = ScheduleKind::Stagnation {
+            patience: 1,
+            boost: 100.0,
+        }
+        .build();
+        let params = schedule.params(&input(0, 1));
+        assert_eq!(params.rate, 1.0);
+        assert_eq!(params.magnitude, 50.0);
+    }
+
+    #[test]
+    fn self_adaptive_step_size_stays_in_bounds() {
+        let mut rng = SimRng::seed_from_u64(7);
+        let activations = [Activation::Relu, Activation::Sigmoid];
+
+        for (initial, bound) in [(1e6, 10.0), (1e-9, 1e-4)] {
+            let mut net = Net::new(&[6, 4, 3], &activations, &mut rng);
+            assert_eq!(net.step_size(), None);
+            net.mutate(0.1, initial, MutationKind::SelfAdaptive, &mut rng);
+            assert_eq!(net.step_size(), Some(bound));
+        }
+
+        let mut net = Net::new(&[6, 4, 3], &activations, &mut rng);
+        for _ in 0..200 {
+            net.mutate(0.1, 0.5, MutationKind::SelfAdaptive, &mut rng);
+            let step_size = net.step_size().unwrap();
+            assert!((1e-4..=10.0).contains(&step_size), "{step_size}");
+        }
+    }
+
+    #[test]
+    fn from_str_fills_missing_params_with_defaults() {
+        assert_eq!(
+            "constant".parse(),
+            Ok(ScheduleKind::Constant {
+                rate: 0.15,
+                magnitude: 0.5
+            })
+        );
+        assert_eq!(
+            "decay(0.3, 0.2)".parse(),
+            Ok(ScheduleKind::Decay {
+                rate: 0.3,
+                magnitude: 0.2,
+                decay: 0.99,
+                floor: 0.1
+            })
+        );
+        assert_eq!(
+            "stagnation".parse(),
+            Ok(ScheduleKind::Stagnation {
+                patience: 20,
+                boost: 2.0
+            })
+        );
+        assert_eq!(" score_banded ".parse(), Ok(ScheduleKind::ScoreBanded));
+    }
+
+    #[test]
+    fn from_str_round_trips_display() {
+        for kind in [
+            ScheduleKind::Constant {
+                rate: 0.1,
+                magnitude: 0.2,
+            },
+            ScheduleKind::Decay {
+                rate: 0.15,
+                magnitude: 0.5,
+                decay: 0.98,
+                floor: 0.2,
+            },
+            ScheduleKind::ScoreBanded,
+            ScheduleKind::Stagnation {
+                patience: 7,
+                boost: 1.5,
+            },
+        ] {
+            assert_eq!(kind.to_string().parse(), Ok(kind));
+        }
+    }
+
+    #[test]
+    fn from_str_rejects_bad_input() {
+        let err = "constant(0.1,x)".parse::<ScheduleKind>().unwrap_err();
+        assert!(err.starts_with("bad schedule params"), "{err}");
+        let err = "decay(0.1".parse::<ScheduleKind>().unwrap_err();
+        assert!(err.starts_with("bad schedule params"), "{err}");
+        let err = "linear".parse::<ScheduleKind>().unwrap_err();
+        assert!(err.starts_with("unknown schedule \"linear\""), "{err}");
+    }
+
+    #[test]
+    fn validate_rejects_out_of_range_params() {
+        let bad = [
+            "constant(0,0.5)",
+            "constant(1.5,0.5)",
+            "decay(0.1,0.5,1.5,0.1)",
+            "decay(0.1,0.5,0.9,0)",
+            "stagnation(0,2)",
+            "stagnation(5,0)",
+        ];
+        for s in bad {
+            let kind: ScheduleKind = s.parse().unwrap();
+            assert!(kind.validate().is_err(), "{s} should be invalid");
+        }
+        assert!(ScheduleKind::default().validate().is_ok());
+    }
+}
@@ -4,25 +4,111 @@
 //! It can only be used for neuro-evolution
 
 use std::{
+    fmt,
     fs::{create_dir_all, File},
-    io::{Read, Write},
+    io::{self, ErrorKind, Read, Write},
+    iter,
     path::Path,
+    str::FromStr,
 };
 
 use rand::Rng;
+use rand_distr::StandardNormal;
 use serde::{Deserialize, Serialize};
 
-use crate::{IS_SAVE_BEST_NET, LOAD_FILE_NAME, SAVE_FILE_NAME};
+use crate::agent::ActionSpace;
+use crate::sensors::SensorKind;
+use crate::GameConfig;
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Net {
     n_inputs: usize,
     layers: Vec<Layer>,
+    // Files saved before the field existed were trained with the legacy pass
+    #[serde(default = "ForwardMode::legacy")]
+    forward: ForwardMode,
+    /// Mutation step size carried by the net, only used by self adaptive mutation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    step_size: Option<f64>,
+    // Older saved nets have no metadata, they used the defaults
+    #[serde(default)]
+    meta: NetMeta,
+}
+
+/// How the agent encodes the inputs and decodes the outputs of the net,
+/// saved with it so `watch` and `eval` read it the way it was trained
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetMeta {
+    pub actions: ActionSpace,
+    pub egocentric: bool,
+    /// In input order
+    pub sensors: Vec<SensorKind>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ForwardMode {
+    /// Each layer feeds its output into the next one
+    Sequential,
+    /// Every layer reads the net inputs and the outputs are concatenated,
+    /// kept so that older saved nets reproduce their trained behavior
+    Legacy,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Activation {
+    #[default]
+    Relu,
+    LeakyRelu,
+    Tanh,
+    Sigmoid,
+    Linear,
+    /// Normalizes the whole layer, only meant for the output layer
+    Softmax,
+}
+
+/// How the weights and biases picked for mutation are changed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MutationKind {
+    /// Uniform noise in `-magnitude..magnitude`
+    #[default]
+    Uniform,
+    /// Normal noise with `magnitude` as the standard deviation
+    Gaussian,
+    /// The value is replaced with a fresh random one
+    Reset,
+    /// Gaussian with a step size that each net carries and evolves,
+    /// the schedule magnitude only seeds it
+    SelfAdaptive,
+}
+
+/// How two parent nets are combined into a child
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum CrossoverKind {
+    /// Each weight and bias comes from either parent with a coin flip
+    #[default]
+    Uniform,
+    /// The flattened genome is cut once, the head comes from one parent
+    SinglePoint,
+    /// The flattened genome is cut at `points` places, alternating parents
+    MultiPoint { points: usize },
+    /// Whole neurons are taken from either parent, keeps hidden units intact
+    Node,
+    /// BLX-α, each gene is sampled around the range spanned by both parents
+    Blend { alpha: f64 },
+    /// The child is a copy of the first parent
+    None,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 struct Layer {
     nodes: Vec<Node>,
+    // Older saved nets used ReLU everywhere
+    #[serde(default)]
+    activation: Activation,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -32,43 +118,126 @@ struct Node {
 }
 
 impl Net {
+    /// `activations` has one entry per layer, excluding the input layer
     #[must_use]
-    pub fn new(layer_sizes: &[usize]) -> Self {
+    pub fn new(layer_sizes: &[usize], activations: &[Activation], rng: &mut impl Rng) -> Self {
         assert!(layer_sizes.len() >= 2, "Need at least 2 layers");
         assert!(
             layer_sizes.iter().all(|&size| size > 0),
             "Empty layers not allowed"
         );
+        assert_eq!(
+            activations.len(),
+            layer_sizes.len() - 1,
+            "Need one activation per layer"
+        );
 
         let mut layers = Vec::new();
         let first_layer_size = *layer_sizes.first().unwrap();
         let mut prev_layer_size = first_layer_size;
 
-        for &layer_size in &layer_sizes[1..] {
-            layers.push(Layer::new(layer_size, prev_layer_size));
+        for (&layer_size, &activation) in layer_sizes[1..].iter().zip(activations) {
+            layers.push(Layer::new(layer_size, prev_layer_size, activation, rng));
             prev_layer_size = layer_size;
         }
 
         Self {
             layers,
             n_inputs: first_layer_size,
+            forward: ForwardMode::Sequential,
+            step_size: None,
+            meta: NetMeta::default(),
         }
     }
 
     #[must_use]
-    pub fn merge(&self, other: &Net) -> Self {
-        assert_eq!(self.layers.len(), other.layers.len());
+    pub fn with_meta(mut self, meta: NetMeta) -> Self {
+        self.meta = meta;
+        self
+    }
 
-        let mut merged_layers = Vec::new();
-        for i in 0..self.layers.len() {
-            let merged_layer = self.layers[i].merge(&other.layers[i]);
-            merged_layers.push(merged_layer);
-        }
+    #[must_use]
+    pub fn meta(&self) -> &NetMeta {
+        &self.meta
+    }
 
-        Net {
-            layers: merged_layers,
-            n_inputs: self.n_inputs,
+    #[must_use]
+    pub fn num_inputs(&self) -> usize {
+        self.n_inputs
+    }
+
+    /// Inputs then the nodes of every layer, eg. [24, 16, 8, 4]
+    #[must_use]
+    pub fn layer_sizes(&self) -> Vec<usize> {
+        iter::once(self.n_inputs)
+            .chain(self.layers.iter().map(|layer| layer.nodes.len()))
+            .collect()
+    }
+
+    /// The child keeps the activations and forward mode of `self`
+    #[must_use]
+    pub fn merge(&self, other: &Net, crossover: CrossoverKind, rng: &mut impl Rng) -> Self {
+        assert!(
+            self.layers.len() == other.layers.len()
+                && self
+                    .layers
+                    .iter()
+                    .zip(&other.layers)
+                    .all(|(l1, l2)| l1.nodes.len() == l2.nodes.len()),
+            "Can only merge nets with the same architecture"
+        );
+
+        let mut child = self.clone();
+        match crossover {
+            CrossoverKind::Uniform => {
+                for (gene, &other_gene) in child.genes_mut().zip(other.genes()) {
+                    if !rng.gen::<bool>() {
+                        *gene = other_gene;
+                    }
+                }
+            }
+            CrossoverKind::SinglePoint => {
+                return self.merge(other, CrossoverKind::MultiPoint { points: 1 }, rng);
+            }
+            CrossoverKind::MultiPoint { points } => {
+                let num_genes = self.genes().count();
+                let mut cuts: Vec<usize> =
+                    (0..points).map(|_| rng.gen_range(0..num_genes)).collect();
+                cuts.sort_unstable();
+
+                // Genes after an odd number of cuts come from the other parent
+                let mut next_cut = 0;
+                for (idx, (gene, &other_gene)) in child.genes_mut().zip(other.genes()).enumerate() {
+                    while next_cut < cuts.len() && cuts[next_cut] <= idx {
+                        next_cut += 1;
+                    }
+                    if next_cut % 2 == 1 {
+                        *gene = other_gene;
+                    }
+                }
+            }
+            CrossoverKind::Node => {
+                let other_nodes = other.layers.iter().flat_map(|l| &l.nodes);
+                let child_nodes = child.layers.iter_mut().flat_map(|l| &mut l.nodes);
+                for (node, other_node) in child_nodes.zip(other_nodes) {
+                    if !rng.gen::<bool>() {
+                        node.clone_from(other_node);
+                    }
+                }
+            }
+            CrossoverKind::Blend { alpha } => {
+                for (gene, &other_gene) in child.genes_mut().zip(other.genes()) {
+                    let (min, max) = (gene.min(other_gene), gene.max(other_gene));
+                    if max > min {
+                        let spread = alpha * (max - min);
+                        *gene = rng.gen_range(min - spread..=max + spread);
+                    }
+                }
+            }
+            CrossoverKind::None => {}
         }
+
+        child
     }
 
     #[must_use]
@@ -79,48 +248,111 @@ impl Net {
             self.n_inputs,
             inputs.len()
         );
-        let output = inputs;
-        self.layers
-            .iter()
-            .flat_map(|layer| layer.predict(&output))
-            .collect()
-    }
 
-    pub fn mutate(&mut self, rate: f64, magnitude: f64) {
-        self.layers
-            .iter_mut()
-            .for_each(|l| l.mutate(rate, magnitude));
+        match self.forward {
+            ForwardMode::Sequential => self
+                .layers
+                .iter()
+                .fold(inputs, |output, layer| layer.predict(&output)),
+            ForwardMode::Legacy => self
+                .layers
+                .iter()
+                .flat_map(|layer| layer.predict(&inputs))
+                .collect(),
+        }
     }
 
-    pub fn save(&self) {
-        if !IS_SAVE_BEST_NET {
-            return;
-        }
+    pub fn mutate(
+        &mut self,
+        rate: f64,
+        magnitude: f64,
+        mutation: MutationKind,
+        rng: &mut impl Rng,
+    ) {
+        let magnitude = match mutation {
+            MutationKind::SelfAdaptive => self.adapt_step_size(magnitude, rng),
+            _ => magnitude,
+        };
 
-        let path = Path::new(SAVE_FILE_NAME);
-        let mut file = match File::create(path) {
-            Ok(file) => file,
-            Err(err) => {
-                if err.kind() == std::io::ErrorKind::NotFound {
-                    create_dir_all(path.parent().unwrap()).unwrap();
-                    File::create(path).unwrap()
-                } else {
-                    panic!("Unexpected error: {err}");
-                }
+        for gene in self.genes_mut() {
+            if rng.gen::<f64>() >= rate {
+                continue;
             }
-        };
 
-        let json: String = serde_json::to_string(&self).unwrap();
-        file.write_all(json.as_bytes())
-            .expect("Failed to write to network file");
+            *gene = match mutation {
+                MutationKind::Uniform => *gene + rng.gen_range(-magnitude..magnitude),
+                MutationKind::Gaussian | MutationKind::SelfAdaptive => {
+                    *gene + magnitude * rng.sample::<f64, _>(StandardNormal)
+                }
+                MutationKind::Reset => rng.gen_range(-1.0..1.0),
+            };
+        }
     }
 
     #[must_use]
-    pub fn load() -> Self {
-        let mut file = File::open(LOAD_FILE_NAME).unwrap();
+    pub fn step_size(&self) -> Option<f64> {
+        self.step_size
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let path = Path::new(path);
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+
+        let mut file = File::create(path)?;
+        let json: String = serde_json::to_string(&self)?;
+        file.write_all(json.as_bytes())
+    }
+
+    pub fn load(path: &str) -> io::Result<Self> {
+        let mut file = File::open(path)?;
         let mut buff = String::new();
-        file.read_to_string(&mut buff).unwrap();
-        serde_json::from_str(&buff).unwrap()
+        file.read_to_string(&mut buff)?;
+        Ok(serde_json::from_str(&buff)?)
+    }
+
+    /// Loads a net and checks its sensors still give the inputs it takes on
+    /// `game`, eg. a grid sensor only fits the board it was trained on
+    pub fn load_for(path: &str, game: &GameConfig) -> io::Result<Self> {
+        let net = Self::load(path)?;
+        let num_inputs = SensorKind::num_inputs(&net.meta.sensors, game);
+        if net.n_inputs != num_inputs {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "{path} takes {} inputs but its sensors give {num_inputs} on a {}x{} board",
+                    net.n_inputs, game.width, game.height
+                ),
+            ));
+        }
+
+        Ok(net)
+    }
+
+    // Log-normal update of the step size, as in evolution strategies
+    fn adapt_step_size(&mut self, initial: f64, rng: &mut impl Rng) -> f64 {
+        let tau = 1.0 / (self.genes().count() as f64).sqrt();
+        let step_size =
+            self.step_size.unwrap_or(initial) * (tau * rng.sample::<f64, _>(StandardNormal)).exp();
+        let step_size = step_size.clamp(1e-4, 10.0);
+        self.step_size = Some(step_size);
+        step_size
+    }
+
+    // Weights then bias of every node, layer by layer
+    fn genes(&self) -> impl Iterator<Item = &f64> {
+        self.layers
+            .iter()
+            .flat_map(|l| &l.nodes)
+            .flat_map(|n| n.weights.iter().chain(iter::once(&n.bias)))
+    }
+
+    fn genes_mut(&mut self) -> impl Iterator<Item = &mut f64> {
+        self.layers
+            .iter_mut()
+            .flat_map(|l| &mut l.nodes)
+            .flat_map(|n| n.weights.iter_mut().chain(iter::once(&mut n.bias)))
     }
 
     // This is for visualization
@@ -135,9 +367,29 @@ impl Net {
     }
 }
 
+impl Default for NetMeta {
+    fn default() -> Self {
+        Self {
+            actions: ActionSpace::Absolute,
+            egocentric: false,
+            sensors: SensorKind::default_suite(),
+        }
+    }
+}
+
+impl ForwardMode {
+    fn legacy() -> Self {
+        Self::Legacy
+    }
+}
+
 impl Layer {
-    fn new(layer_size: usize, prev_layer_size: usize) -> Self {
-        let mut rng = rand::thread_rng();
+    fn new(
+        layer_size: usize,
+        prev_layer_size: usize,
+        activation: Activation,
+        rng: &mut impl Rng,
+    ) -> Self {
         let mut nodes: Vec<Node> = Vec::new();
 
         for _ in 0..layer_size {
@@ -150,32 +402,7 @@ impl Layer {
             nodes.push(Node { weights, bias });
         }
 
-        Self { nodes }
-    }
-
-    fn merge(&self, other: &Layer) -> Self {
-        assert_eq!(self.nodes.len(), other.nodes.len());
-        let mut rng = rand::thread_rng();
-        let mut nodes: Vec<Node> = Vec::new();
-
-        for (node1, node2) in self.nodes.iter().zip(other.nodes.iter()) {
-            let mut merged_weights = Vec::new();
-            for (&weight1, &weight2) in node1.weights.iter().zip(node2.weights.iter()) {
-                let selected_weight = if rng.gen::<bool>() { weight1 } else { weight2 };
-                merged_weights.push(selected_weight);
-            }
-            let merged_bias = if rng.gen::<bool>() {
-                node1.bias
-            } else {
-                node2.bias
-            };
-            nodes.push(Node {
-                weights: merged_weights,
-                bias: merged_bias,
-            });
-        }
-
-        Self { nodes }
+        Self { nodes, activation }
     }
 
     fn predict(&self, inputs: &[f64]) -> Vec<f64> {
@@ -186,26 +413,191 @@ impl Layer {
                 weighted_sum += weight * value;
             }
 
-            // ReLU activation
-            layer_results.push(weighted_sum.max(0.0));
+            layer_results.push(weighted_sum);
         }
 
+        self.activation.apply(&mut layer_results);
         layer_results
     }
+}
 
-    fn mutate(&mut self, rate: f64, magnitude: f64) {
-        let mut rng = rand::thread_rng();
-        for node in &mut self.nodes {
-            for val in &mut node.weights {
-                if rng.gen::<f64>() >= rate {
-                    continue;
-                }
+impl Activation {
+    fn apply(self, values: &mut [f64]) {
+        match self {
+            Self::Relu => values.iter_mut().for_each(|v| *v = v.max(0.0)),
+            Self::LeakyRelu => values
+                .iter_mut()
+                .for_each(|v| *v = if *v > 0.0 { *v } else { 0.01 * *v }),
+            Self::Tanh => values.iter_mut().for_each(|v| *v = v.tanh()),
+            Self::Sigmoid => values
+                .iter_mut()
+                .for_each(|v| *v = 1.0 / (1.0 + (-*v).exp())),
+            Self::Linear => {}
+            Self::Softmax => {
+                // Shift by the max for numerical stability
+                let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+                values.iter_mut().for_each(|v| *v = (*v - max).exp());
+                let sum: f64 = values.iter().sum();
+                values.iter_mut().for_each(|v| *v /= sum);
+            }
+        }
+    }
+}
 
-                *val += rng.gen_range(-magnitude..magnitude);
+impl CrossoverKind {
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Self::MultiPoint { points } if *points == 0 => {
+                Err("multi_point crossover needs at least 1 point".to_string())
             }
-            if rng.gen::<f64>() < rate {
-                node.bias += rng.gen_range(-magnitude..magnitude);
+            Self::Blend { alpha } if !alpha.is_finite() || *alpha < 0.0 => {
+                Err(format!("blend alpha must be positive, got {alpha}"))
             }
+            _ => Ok(()),
         }
     }
 }
+
+// eg. uniform, single_point, multi_point(3), node, blend(0.5), none
+impl FromStr for CrossoverKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, param) = match s.split_once('(') {
+            Some((name, rest)) => (name, rest.strip_suffix(')')),
+            None => (s, None),
+        };
+        let parse_err = || format!("bad crossover param in {s:?}");
+
+        match name.trim() {
+            "uniform" => Ok(Self::Uniform),
+            "single_point" => Ok(Self::SinglePoint),
+            "multi_point" => Ok(Self::MultiPoint {
+                points: param.map_or(Ok(2), |p| p.trim().parse().map_err(|_| parse_err()))?,
+            }),
+            "node" => Ok(Self::Node),
+            "blend" => Ok(Self::Blend {
+                alpha: param.map_or(Ok(0.5), |p| p.trim().parse().map_err(|_| parse_err()))?,
+            }),
+            "none" => Ok(Self::None),
+            _ => Err(format!(
+                "unknown crossover {name:?}, expected uniform, single_point, multi_point(points), node, blend(alpha) or none"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for CrossoverKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Uniform => write!(f, "uniform"),
+            Self::SinglePoint => write!(f, "single_point"),
+            Self::MultiPoint { points } => write!(f, "multi_point({points})"),
+            Self::Node => write!(f, "node"),
+            Self::Blend { alpha } => write!(f, "blend({alpha})"),
+            Self::None => write!(f, "none"),
+        }
+    }
+}
+
+impl FromStr for Activation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "relu" => Ok(Self::Relu),
+            "leaky_relu" => Ok(Self::LeakyRelu),
+            "tanh" => Ok(Self::Tanh),
+            "sigmoid" => Ok(Self::Sigmoid),
+            "linear" => Ok(Self::Linear),
+            "softmax" => Ok(Self::Softmax),
+            _ => Err(format!("unknown activation {s:?}, expected relu, leaky_relu, tanh, sigmoid, linear or softmax")),
+        }
+    }
+}
+
+impl fmt::Display for Activation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Relu => write!(f, "relu"),
+            Self::LeakyRelu => write!(f, "leaky_relu"),
+            Self::Tanh => write!(f, "tanh"),
+            Self::Sigmoid => write!(f, "sigmoid"),
+            Self::Linear => write!(f, "linear"),
+            Self::Softmax => write!(f, "softmax"),
+        }
+    }
+}
+
+impl FromStr for MutationKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "uniform" => Ok(Self::Uniform),
+            "gaussian" => Ok(Self::Gaussian),
+            "reset" => Ok(Self::Reset),
+            "self_adaptive" => Ok(Self::SelfAdaptive),
+            _ => Err(format!(
+                "unknown mutation {s:?}, expected uniform, gaussian, reset or self_adaptive"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for MutationKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Uniform => write!(f, "uniform"),
+            Self::Gaussian => write!(f, "gaussian"),
+            Self::Reset => write!(f, "reset"),
+            Self::SelfAdaptive => write!(f, "self_adaptive"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::SimRng;
+
+    #[test]
+    fn blend_without_alpha_lands_between_the_parents() {
+        let mut rng = SimRng::seed_from_u64(7);
+        let activations = [Activation::Relu, Activation::Sigmoid];
+        let a = Net::new(&[6, 4, 3], &activations, &mut rng);
+        let b = Net::new(&[6, 4, 3], &activations, &mut rng);
+
+        for _ in 0..20 {
+            let child = a.merge(&b, CrossoverKind::Blend { alpha: 0.0 }, &mut rng);
+            for ((&gene, &a_gene), &b_gene) in child.genes().zip(a.genes()).zip(b.genes()) {
+                let (min, max) = (a_gene.min(b_gene), a_gene.max(b_gene));
+                assert!(min < max);
+                assert!(min < gene && gene < max, "{gene} not in {min}..{max}");
+            }
+        }
+    }
+
+    #[test]
+    fn layer_sizes_follow_the_arch() {
+        let mut rng = SimRng::seed_from_u64(7);
+        let activations = [Activation::Relu, Activation::Sigmoid];
+        let net = Net::new(&[24, 16, 4], &activations, &mut rng);
+        assert_eq!(net.layer_sizes(), [24, 16, 4]);
+    }
+
+    #[test]
+    fn blend_keeps_genes_the_parents_share() {
+        let mut rng = SimRng::seed_from_u64(7);
+        let a = Net::new(
+            &[6, 4, 3],
+            &[Activation::Relu, Activation::Sigmoid],
+            &mut rng,
+        );
+
+        let child = a.merge(&a, CrossoverKind::Blend { alpha: 0.5 }, &mut rng);
+        assert!(child.genes().eq(a.genes()));
+    }
+}
@@ -1,48 +1,185 @@
 //! Population
 //! A group of simulation agents
 
-use std::cmp::Ordering;
+use std::io;
+use std::sync::Arc;
 
-use rand::distributions::{Distribution, WeightedIndex};
 use rand::Rng;
 use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 
 use crate::agent::Agent;
-use crate::nn::Net;
-use crate::{
-    GRID_SIZE, IS_LOAD_SAVED_DATA, NN_ARCH, NUM_AGENTS, POP_NUM_RANDOM, POP_RETAINED,
-    POP_RETAINED_MUTATED, POP_ROULETTE, POP_TOURNAMENT,
-};
+use crate::fitness::FitnessFn;
+use crate::game::OutcomeCounts;
+use crate::mutation::{MutationSchedule, ScheduleInput};
+use crate::nn::{MutationKind, Net};
+use crate::reproduction::{stage_counts, Parents, Stage};
+use crate::{seeded_rng, RngStream, SimConfig, SimRng};
 
 pub struct Population {
     pub mutation_magnitude: f64,
     pub mutation_rate: f64,
 
     agents: Vec<Agent>,
+    config: SimConfig,
+    fitness_fn: Arc<dyn FitnessFn>,
+    pipeline: Vec<Stage>,
+    schedule: Box<dyn MutationSchedule>,
+    seed: u64,
+    gen_count: usize,
+    best_score: usize,
+    stagnant_gens: usize,
 }
 
-impl Default for Population {
-    fn default() -> Self {
-        Self::new()
-    }
+/// Everything needed to rebuild a population at the start of a generation,
+/// the games are derived from the seed so only the brains are stored
+#[derive(Serialize, Deserialize)]
+pub struct PopulationState {
+    pub gen_count: usize,
+    pub mutation_rate: f64,
+    pub mutation_magnitude: f64,
+    #[serde(default)]
+    pub best_score: usize,
+    #[serde(default)]
+    pub stagnant_gens: usize,
+    pub brains: Vec<Net>,
 }
 
 impl Population {
-    #[must_use]
-    pub fn new() -> Self {
-        let mut agents = Vec::new();
-        for _ in 0..NUM_AGENTS {
-            agents.push(Agent::new(IS_LOAD_SAVED_DATA));
-        }
+    /// All randomness is derived from `seed`, so the same seed and config
+    /// always evolve the same population
+    pub fn new(config: &SimConfig, seed: u64) -> io::Result<Self> {
+        let saved_net = if config.data.is_load_saved_data {
+            Some(Net::load_for(&config.data.load_file_name, &config.game)?)
+        } else {
+            None
+        };
+
+        let mut rng = seeded_rng(seed, RngStream::Population, &[0]);
+        let brains = (0..config.sim.num_agents)
+            .map(|_| match &saved_net {
+                Some(net) => {
+                    let mut net = net.clone();
+                    net.mutate(0.0, 0.1, MutationKind::Uniform, &mut rng);
+                    net
+                }
+                None => Self::random_net(config, &mut rng),
+            })
+            .collect();
 
-        Self {
+        let mut pop = Self {
             // rate & mag will be reset before use
             mutation_rate: 0.1,
             mutation_magnitude: 0.1,
-            agents,
+            agents: Vec::new(),
+            config: config.clone(),
+            fitness_fn: config.pop.fitness.build(),
+            pipeline: Self::build_pipeline(config),
+            schedule: config.pop.mutation_schedule.build(),
+            seed,
+            gen_count: 0,
+            best_score: 0,
+            stagnant_gens: 0,
+        };
+        pop.agents = pop.create_agents(brains);
+
+        Ok(pop)
+    }
+
+    #[must_use]
+    pub fn from_state(config: &SimConfig, seed: u64, state: PopulationState) -> Self {
+        let mut pop = Self {
+            mutation_rate: state.mutation_rate,
+            mutation_magnitude: state.mutation_magnitude,
+            agents: Vec::new(),
+            config: config.clone(),
+            fitness_fn: config.pop.fitness.build(),
+            pipeline: Self::build_pipeline(config),
+            schedule: config.pop.mutation_schedule.build(),
+            seed,
+            gen_count: state.gen_count,
+            best_score: state.best_score,
+            stagnant_gens: state.stagnant_gens,
+        };
+        pop.agents = pop.create_agents(state.brains);
+
+        pop
+    }
+
+    /// Brains don't change while a generation is played,
+    /// so the state can be taken at any point of the generation
+    #[must_use]
+    pub fn state(&self) -> PopulationState {
+        PopulationState {
+            gen_count: self.gen_count,
+            mutation_rate: self.mutation_rate,
+            mutation_magnitude: self.mutation_magnitude,
+            best_score: self.best_score,
+            stagnant_gens: self.stagnant_gens,
+            brains: self.agents.iter().map(|a| a.brain.clone()).collect(),
         }
     }
 
+    #[must_use]
+    pub fn agents(&self) -> &[Agent] {
+        &self.agents
+    }
+
+    /// Replaces the fitness function picked by the config
+    pub fn set_fitness_fn(&mut self, fitness_fn: Arc<dyn FitnessFn>) {
+        self.fitness_fn = fitness_fn;
+    }
+
+    /// Replaces the reproduction pipeline picked by the config
+    pub fn set_pipeline(&mut self, pipeline: Vec<Stage>) {
+        self.pipeline = pipeline;
+    }
+
+    /// Replaces the mutation schedule picked by the config
+    pub fn set_mutation_schedule(&mut self, schedule: Box<dyn MutationSchedule>) {
+        self.schedule = schedule;
+    }
+
+    /// Highest score possible on the board the agents play on
+    #[must_use]
+    pub fn max_score(&self) -> usize {
+        self.agents
+            .first()
+            .map_or_else(|| self.config.game.max_score(), |a| a.game.max_score())
+    }
+
+    /// Board width and height the agents play on
+    #[must_use]
+    pub fn board_size(&self) -> (i32, i32) {
+        self.agents
+            .first()
+            .map_or((self.config.game.width, self.config.game.height), |a| {
+                (a.game.width(), a.game.height())
+            })
+    }
+
+    /// Agents of the next generation play on this board, the best score
+    /// and stagnation count start over with them
+    pub fn set_board(&mut self, width: i32, height: i32) {
+        self.config.game.width = width;
+        self.config.game.height = height;
+    }
+
+    /// How the games of the current generation ended
+    #[must_use]
+    pub fn outcome_counts(&self) -> OutcomeCounts {
+        self.agents.iter().map(|a| a.game.outcome).collect()
+    }
+
+    /// Fitness of every agent, in agent order
+    #[must_use]
+    pub fn fitness(&self) -> Vec<f32> {
+        self.agents
+            .par_iter()
+            .map(|a| self.fitness_fn.fitness(&a.game))
+            .collect()
+    }
+
     pub fn update(&mut self) -> usize {
         let agents_dead = self
             .agents
@@ -53,34 +190,40 @@ impl Population {
             })
             .sum::<usize>();
 
-        NUM_AGENTS - agents_dead
+        self.agents.len() - agents_dead
     }
 
     pub fn reset(&mut self) {
+        self.gen_count += 1;
         self.reset_pop();
     }
 
     #[must_use]
     pub fn get_gen_summary(&self) -> (Net, usize) {
+        let best = self.best_agent();
+        (best.brain.to_owned(), best.game.score())
+    }
+
+    /// The first agent with the highest score
+    #[must_use]
+    pub fn best_agent(&self) -> &Agent {
         let mut max_score = 0;
-        let mut best_net = None;
+        let mut best = &self.agents[0];
 
         for a in &self.agents {
             let score = a.game.score();
             if score > max_score {
                 max_score = score;
-                best_net = Some(&a.brain);
+                best = a;
             }
         }
 
-        if let Some(net) = best_net {
-            return (net.to_owned(), max_score);
-        }
-
-        (Net::new(&NN_ARCH), max_score)
+        best
     }
 
     fn reset_pop(&mut self) {
+        let mut rng = seeded_rng(self.seed, RngStream::Population, &[self.gen_count as u64]);
+
         // Calc mutation rate and mag
         let gen_max_score = self
             .agents
@@ -88,125 +231,108 @@ impl Population {
             .map(|a| a.game.score())
             .max()
             .unwrap_or(0);
-        let (mutation_mag, mutation_rate) = Self::get_mutation_params(gen_max_score as f64);
-
-        // Sort agents based on their fitness
-        let mut agents_sorted = self.agents.clone();
-        agents_sorted.sort_by(|a, b| b.partial_cmp(a).unwrap_or(Ordering::Equal));
-
-        // Population Distribution
-        let num_elite = (NUM_AGENTS as f32 * POP_RETAINED) as usize;
-        let num_roulette = (NUM_AGENTS as f32 * POP_ROULETTE) as usize;
-        let mut num_tournament = (NUM_AGENTS as f32 * POP_TOURNAMENT) as usize;
-        let num_mutated = (NUM_AGENTS as f32 * POP_RETAINED_MUTATED) as usize;
-        let num_random = (NUM_AGENTS as f32 * POP_NUM_RANDOM) as usize;
-
-        // Elitism
-        // Preserve best performing agents
-        // Hels maintain high fitness levels within the population
-        let mut new_agents: Vec<_> = agents_sorted
-            .iter()
-            .take(num_elite)
-            .map(|agent| Agent::with_brain(agent.brain.clone()))
-            .collect();
-
-        new_agents.reserve(NUM_AGENTS - num_elite);
-
-        // Roulette Selection (or Fitness Proportionate Selection)
-        // Each agent is selected with a probability proportional to its fitness
-        let gene_pool = self.generate_gene_pool();
-        if let Some(pool) = gene_pool {
-            let mut rng = rand::thread_rng();
-            for _ in 0..num_roulette as i32 {
-                let rand_parent_1 = &self.agents[pool.sample(&mut rng)];
-                let rand_parent_2 = &self.agents[pool.sample(&mut rng)];
-                let mut new_brain = rand_parent_1.brain.merge(&rand_parent_2.brain);
-                new_brain.mutate(mutation_rate, mutation_mag);
-
-                let new_agent = Agent::with_brain(new_brain);
-                new_agents.push(new_agent);
-            }
+        if gen_max_score > self.best_score {
+            self.best_score = gen_max_score;
+            self.stagnant_gens = 0;
         } else {
-            num_tournament += num_roulette;
+            self.stagnant_gens += 1;
         }
-
-        // Tournament Selection
-        // Fittest agents among a randomly selected group (tournament)
-        // Tournament Size (TS) controls the balance between exploration and exploitation
-        // Smaller TS -> More exploration
-        let tournament_size = 5;
-        for _ in 0..num_tournament {
-            let winner = self.tournament_selection(tournament_size);
-            let mut new_brain = winner.brain.clone();
-            new_brain.mutate(mutation_rate, mutation_mag);
-            new_agents.push(Agent::with_brain(new_brain));
+        let mutation = self.schedule.params(&ScheduleInput {
+            gen_count: self.gen_count,
+            gen_max_score,
+            max_score: self.max_score(),
+            stagnant_gens: self.stagnant_gens,
+        });
+
+        // The next generation plays on a new curriculum board,
+        // stagnation is measured against scores on that board only
+        if self.board_size() != (self.config.game.width, self.config.game.height) {
+            self.best_score = 0;
+            self.stagnant_gens = 0;
         }
 
-        // Mutational Elitism
-        // Allows for incremental improvements to already good solutions
-        new_agents.extend(agents_sorted.iter().take(num_mutated).map(|agent| {
-            let mut old_brain = agent.brain.clone();
-            old_brain.mutate(mutation_rate, mutation_mag);
-            Agent::with_brain(old_brain)
-        }));
-
-        // Full random
-        // Diversify the gene pool
-        new_agents.extend(
-            self.agents
-                .iter()
-                .take(num_random)
-                .map(|_| Agent::new(false)),
+        let fitness = self.fitness();
+        let parents = Parents::new(
+            self.agents.iter().map(|a| &a.brain).collect(),
+            &fitness,
+            mutation,
+            self.config.pop.mutation,
         );
 
-        self.agents = new_agents;
-        self.mutation_magnitude = mutation_mag;
-        self.mutation_rate = mutation_rate;
+        // Each stage fills its share of the new population
+        let num_agents = self.config.sim.num_agents;
+        let mut new_brains = Vec::with_capacity(num_agents);
+        let counts = stage_counts(&self.pipeline, num_agents);
+        for (stage, count) in self.pipeline.iter().zip(counts) {
+            new_brains.extend(stage.op.reproduce(&parents, count, &mut rng));
+        }
+
+        self.agents = self.create_agents(new_brains);
+        self.mutation_magnitude = mutation.magnitude;
+        self.mutation_rate = mutation.rate;
     }
 
-    fn tournament_selection(&self, tournament_size: usize) -> &Agent {
-        let mut rng = rand::thread_rng();
-        let mut best_agent = &self.agents[rng.gen_range(0..self.agents.len())];
+    /// Each game gets its own seed so agents can update in parallel
+    /// without the thread count affecting the run
+    fn create_agents(&self, brains: Vec<Net>) -> Vec<Agent> {
+        brains
+            .into_iter()
+            .enumerate()
+            .map(|(idx, brain)| {
+                let seed = seeded_rng(
+                    self.seed,
+                    RngStream::Game,
+                    &[self.gen_count as u64, idx as u64],
+                )
+                .gen();
+                Agent::with_brain(brain, &self.config, seed)
+            })
+            .collect()
+    }
 
-        for _ in 0..tournament_size {
-            let agent = &self.agents[rng.gen_range(0..self.agents.len())];
-            if agent.fitness() > best_agent.fitness() {
-                best_agent = agent;
-            }
-        }
+    fn build_pipeline(config: &SimConfig) -> Vec<Stage> {
+        config
+            .pop
+            .pipeline
+            .iter()
+            .map(|stage| stage.build(config))
+            .collect()
+    }
 
-        best_agent
+    fn random_net(config: &SimConfig, rng: &mut SimRng) -> Net {
+        Net::new(&config.nn.arch, &config.nn.activations, rng).with_meta(config.nn.meta())
     }
+}
 
-    fn generate_gene_pool(&self) -> Option<WeightedIndex<f32>> {
-        let mut max_fitness = 0.0;
-        let mut weights = Vec::new();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        for a in &self.agents {
-            let fitness = a.fitness();
-            if fitness > max_fitness {
-                max_fitness = fitness;
-            }
+    fn population() -> Population {
+        let mut config = SimConfig::default();
+        config.sim.num_agents = 10;
+        Population::new(&config, 7).unwrap()
+    }
 
-            if fitness.is_finite() {
-                weights.push(fitness);
-            }
-        }
-        weights
-            .iter_mut()
-            .for_each(|i| *i = (*i / max_fitness) * 100.0);
+    #[test]
+    fn stagnation_starts_over_on_a_new_board() {
+        let mut pop = population();
+        pop.best_score = 50;
+        pop.stagnant_gens = 9;
+        pop.set_board(10, 10);
+        pop.reset();
 
-        WeightedIndex::new(&weights).ok()
+        assert_eq!((pop.best_score, pop.stagnant_gens), (0, 0));
+        assert_eq!(pop.board_size(), (10, 10));
     }
 
-    fn get_mutation_params(gen_max: f64) -> (f64, f64) {
-        let max_score = f64::from((GRID_SIZE - 1) * (GRID_SIZE - 1));
-        if gen_max > 0.75 * max_score {
-            (0.1, 0.15)
-        } else if gen_max > 0.5 * max_score {
-            (0.1, 0.25)
-        } else {
-            (0.5, 0.15)
-        }
+    #[test]
+    fn stagnation_carries_on_for_the_same_board() {
+        let mut pop = population();
+        pop.best_score = 50;
+        pop.stagnant_gens = 9;
+        pop.reset();
+
+        assert_eq!((pop.best_score, pop.stagnant_gens), (50, 10));
     }
 }
@@ -0,0 +1,379 @@
+//! Replay
+//! Recorded games that play back exactly, without the rng or the brain
+
+use std::{
+    fs::{self, create_dir_all},
+    io::{self, ErrorKind},
+    path::Path,
+    sync::Arc,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::game::{Game, GameOutcome, GameRecord};
+use crate::level::Level;
+use crate::GameConfig;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Replay {
+    pub width: i32,
+    pub height: i32,
+    #[serde(default)]
+    pub wrap: bool,
+    /// Name and contents of the level file the game was played on
+    #[serde(default)]
+    pub level: Option<(String, String)>,
+    /// Generation the game was played in, when it comes from a training run
+    #[serde(default)]
+    pub gen_count: Option<usize>,
+    #[serde(flatten)]
+    pub record: GameRecord,
+    pub outcome: GameOutcome,
+    pub score: usize,
+}
+
+/// Steps through a replay, forwards or backwards
+pub struct Playback {
+    replay: Replay,
+    config: GameConfig,
+    game: Game,
+    pickups: Vec<usize>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Seek {
+    Back,
+    Forward,
+    Start,
+    End,
+}
+
+impl Replay {
+    #[must_use]
+    pub fn from_game(game: &Game, gen_count: Option<usize>) -> Self {
+        Self {
+            width: game.width(),
+            height: game.height(),
+            wrap: game.is_wrap(),
+            level: game
+                .level()
+                .map(|level| (level.name.clone(), level.to_string())),
+            gen_count,
+            record: game.record().clone(),
+            outcome: game.outcome,
+            score: game.score(),
+        }
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let path = Path::new(path);
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_string(self)?)
+    }
+
+    pub fn load(path: &str) -> io::Result<Self> {
+        let buff = fs::read_to_string(path)?;
+        let replay: Self = serde_json::from_str(&buff)?;
+        if replay.record.start_body.is_empty() || replay.record.foods.is_empty() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("replay {path} has no start state"),
+            ));
+        }
+
+        Ok(replay)
+    }
+
+    /// The board the game was played on
+    pub fn game_config(&self) -> io::Result<GameConfig> {
+        let maps = match &self.level {
+            Some((name, contents)) => {
+                vec![Arc::new(Level::parse(name, contents).map_err(|err| {
+                    io::Error::new(ErrorKind::InvalidData, err)
+                })?)]
+            }
+            None => Vec::new(),
+        };
+
+        Ok(GameConfig {
+            width: self.width,
+            height: self.height,
+            wrap: self.wrap,
+            levels: maps.iter().map(|level| level.name.clone()).collect(),
+            maps,
+        })
+    }
+}
+
+impl Playback {
+    pub fn new(replay: Replay) -> io::Result<Self> {
+        let config = replay.game_config()?;
+        Ok(Self::with_config(replay, config))
+    }
+
+    /// The moves played so far in a game that may still be running
+    #[must_use]
+    pub fn from_game(game: &Game) -> Self {
+        let config = GameConfig {
+            width: game.width(),
+            height: game.height(),
+            wrap: game.is_wrap(),
+            levels: game.level().map(|l| l.name.clone()).into_iter().collect(),
+            maps: game
+                .level()
+                .map(|l| Arc::new(l.clone()))
+                .into_iter()
+                .collect(),
+        };
+        Self::with_config(Replay::from_game(game, None), config)
+    }
+
+    fn with_config(replay: Replay, config: GameConfig) -> Self {
+        let mut playback = Self {
+            game: Game::from_record(&config, config.maps.first().cloned(), &replay.record),
+            replay,
+            config,
+            pickups: Vec::new(),
+        };
+
+        let mut score = playback.game.score();
+        while playback.step_forward() {
+            if playback.game.score() > score {
+                score = playback.game.score();
+                playback.pickups.push(playback.step());
+            }
+        }
+        playback.restart();
+
+        playback
+    }
+
+    #[must_use]
+    pub fn replay(&self) -> &Replay {
+        &self.replay
+    }
+
+    #[must_use]
+    pub fn game(&self) -> &Game {
+        &self.game
+    }
+
+    /// Moves played so far
+    #[must_use]
+    pub fn step(&self) -> usize {
+        self.game.record().moves.len()
+    }
+
+    #[must_use]
+    pub fn num_steps(&self) -> usize {
+        self.replay.record.moves.len()
+    }
+
+    /// Steps where the snake ate
+    #[must_use]
+    pub fn pickups(&self) -> &[usize] {
+        &self.pickups
+    }
+
+    #[must_use]
+    pub fn is_done(&self) -> bool {
+        self.game.is_dead || self.step() >= self.num_steps()
+    }
+
+    /// Returns false once there are no moves left
+    pub fn step_forward(&mut self) -> bool {
+        if self.is_done() {
+            return false;
+        }
+
+        let dir = self.replay.record.moves[self.step()];
+        self.game.update(dir);
+
+        // Starvation is decided by the agent, not the game
+        if self.step() == self.num_steps() && !self.game.is_dead {
+            if let GameOutcome::Starved = self.replay.outcome {
+                self.game.end(GameOutcome::Starved);
+            }
+        }
+
+        true
+    }
+
+    /// Back to the first move
+    pub fn restart(&mut self) {
+        self.game = Game::from_record(
+            &self.config,
+            self.config.maps.first().cloned(),
+            &self.replay.record,
+        );
+    }
+
+    /// Going back replays the game from the start up to the step
+    pub fn go_to(&mut self, step: usize) {
+        if step < self.step() {
+            self.restart();
+        }
+        while self.step() < step && self.step_forward() {}
+    }
+
+    pub fn seek(&mut self, seek: Seek) {
+        match seek {
+            Seek::Back => self.go_to(self.step().saturating_sub(1)),
+            Seek::Forward => {
+                self.step_forward();
+            }
+            Seek::Start => self.restart(),
+            Seek::End => self.go_to(self.num_steps()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FourDirs, Point};
+
+    // Heads for the food, avoiding walls and the body while it can
+    fn greedy_dir(game: &Game) -> FourDirs {
+        let dirs = [
+            FourDirs::Left,
+            FourDirs::Right,
+            FourDirs::Bottom,
+            FourDirs::Top,
+        ];
+        let dist = |dir: FourDirs| {
+            let next = game.step(game.head, dir.value());
+            let is_safe = !game.is_wall(next) && !game.is_snake_body(next);
+            let to_food = (next.x - game.food.x).abs() + (next.y - game.food.y).abs();
+            (!is_safe, to_food)
+        };
+        dirs.into_iter().min_by_key(|&dir| dist(dir)).unwrap()
+    }
+
+    fn play(config: &GameConfig, seed: u64, max_steps: usize) -> Game {
+        let mut game = Game::new(config, seed);
+        for _ in 0..max_steps {
+            if game.is_dead {
+                break;
+            }
+            game.update(greedy_dir(&game));
+        }
+        game
+    }
+
+    fn assert_plays_back(game: &Game) {
+        let replay = Replay::from_game(game, Some(1));
+        let json = serde_json::to_string(&replay).unwrap();
+        let mut playback = Playback::new(serde_json::from_str(&json).unwrap()).unwrap();
+        playback.seek(Seek::End);
+
+        let played = playback.game();
+        assert_eq!(played.score(), game.score());
+        assert_eq!(played.outcome, game.outcome);
+        assert_eq!(played.body, game.body);
+        assert_eq!(played.food, game.food);
+        assert_eq!(
+            played.level().map(|l| &l.name),
+            game.level().map(|l| &l.name)
+        );
+        assert_eq!(playback.pickups().len(), game.food_eaten());
+    }
+
+    #[test]
+    fn replays_play_back_exactly() {
+        let config = GameConfig::default();
+        for seed in 0..10 {
+            let game = play(&config, seed, 1000);
+            assert!(game.is_dead);
+            assert!(game.food_eaten() > 0);
+            assert_plays_back(&game);
+        }
+    }
+
+    #[test]
+    fn starved_games_play_back_starved() {
+        let config = GameConfig::default();
+        let mut game = play(&config, 3, 40);
+        assert!(!game.is_dead);
+
+        // As the agent does once the step limit is hit
+        game.end(GameOutcome::Starved);
+        assert_plays_back(&game);
+    }
+
+    const PILLARS: &str = "\
+spawn 3 1 right
+##########
+#........#
+#..#..#..#
+#........#
+#........#
+#..#..#..#
+#........#
+#........#
+##########
+";
+
+    #[test]
+    fn level_games_play_back_exactly() {
+        let level = Level::parse("pillars", PILLARS).unwrap();
+        let config = GameConfig {
+            width: level.width,
+            height: level.height,
+            levels: vec![level.name.clone()],
+            maps: vec![Arc::new(level)],
+            ..Default::default()
+        };
+
+        for seed in 0..5 {
+            let game = play(&config, seed, 1000);
+            assert!(game.food_eaten() > 0);
+            assert_plays_back(&game);
+
+            let replay = Replay::from_game(&game, None);
+            assert_eq!(
+                replay.game_config().unwrap().maps[0].spawn,
+                Some((Point::new(3, 1), FourDirs::Right))
+            );
+        }
+    }
+
+    #[test]
+    fn games_on_several_maps_play_back_on_their_own() {
+        let pillars = Level::parse("pillars", PILLARS).unwrap();
+        let wall = Level::parse(
+            "wall",
+            "\
+spawn 6 7 left
+##########
+#........#
+#........#
+#........#
+#.######.#
+#........#
+#........#
+#........#
+##########
+",
+        )
+        .unwrap();
+        let config = GameConfig {
+            width: pillars.width,
+            height: pillars.height,
+            levels: vec![pillars.name.clone(), wall.name.clone()],
+            maps: vec![Arc::new(pillars), Arc::new(wall)],
+            ..Default::default()
+        };
+
+        let mut played_on = Vec::new();
+        for seed in 0..10 {
+            let game = play(&config, seed, 1000);
+            played_on.push(game.level().unwrap().name.clone());
+            assert_plays_back(&game);
+        }
+        assert!(played_on.iter().any(|name| name == "pillars"));
+        assert!(played_on.iter().any(|name| name == "wall"));
+    }
+}
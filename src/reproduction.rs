@@ -0,0 +1,367 @@
+//! Reproduction
+//! Operators that breed the next generation, they run in order as a pipeline
+//! and each one fills its fraction of the new population
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::mutation::MutationParams;
+use crate::nn::{Activation, CrossoverKind, MutationKind, Net, NetMeta};
+use crate::selection::{Roulette, SelectionKind, SelectionStrategy};
+use crate::{SimConfig, SimRng};
+
+/// The finished generation that the next one is bred from
+pub struct Parents<'a> {
+    pub brains: Vec<&'a Net>,
+    pub fitness: &'a [f32],
+    /// Indices into `brains`, best first
+    pub ranked: Vec<usize>,
+    pub mutation: MutationParams,
+    pub mutation_kind: MutationKind,
+}
+
+pub trait Reproduction: Send + Sync {
+    fn reproduce(&self, parents: &Parents, count: usize, rng: &mut SimRng) -> Vec<Net>;
+}
+
+pub struct Stage {
+    /// Fraction of the population this stage produces
+    pub fraction: f64,
+    pub op: Box<dyn Reproduction>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StageConfig {
+    pub fraction: f64,
+    #[serde(flatten)]
+    pub op: ReproductionKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum ReproductionKind {
+    Elite,
+    MutatedElite,
+    Crossover { selection: SelectionKind },
+    Mutate { selection: SelectionKind },
+    Random,
+}
+
+/// Preserve the best performing agents as is.
+/// Helps maintain high fitness levels within the population
+pub struct Elitism;
+
+/// Mutated copies of the best agents.
+/// Allows for incremental improvements to already good solutions
+pub struct MutatedElitism;
+
+/// Two selected parents are merged and the child is mutated.
+/// When the selection can't rank the parents (eg. roulette with no positive fitness)
+/// the stage makes mutated copies of tournament winners instead, like the original sim
+pub struct Crossover {
+    pub selection: Box<dyn SelectionStrategy>,
+    pub crossover: CrossoverKind,
+}
+
+/// A mutated copy of a single selected parent
+pub struct Mutation {
+    pub selection: Box<dyn SelectionStrategy>,
+}
+
+/// Fresh random networks, diversifies the gene pool
+pub struct RandomFill {
+    pub arch: Vec<usize>,
+    pub activations: Vec<Activation>,
+    pub meta: NetMeta,
+}
+
+/// How many agents each stage breeds, rounded on the running total
+/// so the stages always add up to `num_agents`
+#[must_use]
+pub fn stage_counts(pipeline: &[Stage], num_agents: usize) -> Vec<usize> {
+    let mut counts = Vec::with_capacity(pipeline.len());
+    let mut cumulative = 0.0;
+    let mut filled = 0;
+    for (i, stage) in pipeline.iter().enumerate() {
+        cumulative += stage.fraction;
+        let end = if i + 1 == pipeline.len() {
+            num_agents
+        } else {
+            ((num_agents as f64 * cumulative).round() as usize).clamp(filled, num_agents)
+        };
+        counts.push(end - filled);
+        filled = end;
+    }
+
+    counts
+}
+
+impl<'a> Parents<'a> {
+    #[must_use]
+    pub fn new(
+        brains: Vec<&'a Net>,
+        fitness: &'a [f32],
+        mutation: MutationParams,
+        mutation_kind: MutationKind,
+    ) -> Self {
+        // Stable sort, ties keep the agent order
+        let mut ranked: Vec<usize> = (0..brains.len()).collect();
+        ranked.sort_by(|&a, &b| {
+            fitness[b]
+                .partial_cmp(&fitness[a])
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        Self {
+            brains,
+            fitness,
+            ranked,
+            mutation,
+            mutation_kind,
+        }
+    }
+
+    fn mutated(&self, mut brain: Net, rng: &mut SimRng) -> Net {
+        brain.mutate(
+            self.mutation.rate,
+            self.mutation.magnitude,
+            self.mutation_kind,
+            rng,
+        );
+        brain
+    }
+}
+
+impl Reproduction for Elitism {
+    fn reproduce(&self, parents: &Parents, count: usize, _rng: &mut SimRng) -> Vec<Net> {
+        parents
+            .ranked
+            .iter()
+            .take(count)
+            .map(|&idx| parents.brains[idx].clone())
+            .collect()
+    }
+}
+
+impl Reproduction for MutatedElitism {
+    fn reproduce(&self, parents: &Parents, count: usize, rng: &mut SimRng) -> Vec<Net> {
+        parents
+            .ranked
+            .iter()
+            .take(count)
+            .map(|&idx| parents.mutated(parents.brains[idx].clone(), rng))
+            .collect()
+    }
+}
+
+impl Reproduction for Crossover {
+    fn reproduce(&self, parents: &Parents, count: usize, rng: &mut SimRng) -> Vec<Net> {
+        if !self.selection.can_select(parents.fitness) {
+            let fallback = Mutation {
+                selection: Box::new(Roulette::fallback()),
+            };
+            return fallback.reproduce(parents, count, rng);
+        }
+
+        let selected = self.selection.select(parents.fitness, count * 2, rng);
+        selected
+            .chunks_exact(2)
+            .map(|pair| {
+                let child =
+                    parents.brains[pair[0]].merge(parents.brains[pair[1]], self.crossover, rng);
+                parents.mutated(child, rng)
+            })
+            .collect()
+    }
+}
+
+impl Reproduction for Mutation {
+    fn reproduce(&self, parents: &Parents, count: usize, rng: &mut SimRng) -> Vec<Net> {
+        let selected = self.selection.select(parents.fitness, count, rng);
+        selected
+            .into_iter()
+            .map(|idx| parents.mutated(parents.brains[idx].clone(), rng))
+            .collect()
+    }
+}
+
+impl Reproduction for RandomFill {
+    fn reproduce(&self, _parents: &Parents, count: usize, rng: &mut SimRng) -> Vec<Net> {
+        (0..count)
+            .map(|_| Net::new(&self.arch, &self.activations, rng).with_meta(self.meta.clone()))
+            .collect()
+    }
+}
+
+impl StageConfig {
+    #[must_use]
+    pub fn new(fraction: f64, op: ReproductionKind) -> Self {
+        Self { fraction, op }
+    }
+
+    #[must_use]
+    pub fn build(&self, config: &SimConfig) -> Stage {
+        let op: Box<dyn Reproduction> = match self.op {
+            ReproductionKind::Elite => Box::new(Elitism),
+            ReproductionKind::MutatedElite => Box::new(MutatedElitism),
+            ReproductionKind::Crossover { selection } => Box::new(Crossover {
+                selection: selection.build(),
+                crossover: config.pop.crossover,
+            }),
+            ReproductionKind::Mutate { selection } => Box::new(Mutation {
+                selection: selection.build(),
+            }),
+            ReproductionKind::Random => Box::new(RandomFill {
+                arch: config.nn.arch.clone(),
+                activations: config.nn.activations.clone(),
+                meta: config.nn.meta(),
+            }),
+        };
+
+        Stage {
+            fraction: self.fraction,
+            op,
+        }
+    }
+
+    /// The pipeline the simulation started with
+    #[must_use]
+    pub fn default_pipeline() -> Vec<Self> {
+        vec![
+            Self::new(0.1, ReproductionKind::Elite),
+            Self::new(
+                0.6,
+                ReproductionKind::Crossover {
+                    selection: SelectionKind::Roulette,
+                },
+            ),
+            Self::new(
+                0.1,
+                ReproductionKind::Mutate {
+                    // The best of 6 like the original sim
+                    selection: SelectionKind::Tournament { size: 6 },
+                },
+            ),
+            Self::new(0.0, ReproductionKind::MutatedElite),
+            Self::new(0.2, ReproductionKind::Random),
+        ]
+    }
+
+    fn selection(&self) -> Option<SelectionKind> {
+        match self.op {
+            ReproductionKind::Crossover { selection } | ReproductionKind::Mutate { selection } => {
+                Some(selection)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.fraction) {
+            return Err(format!("stage {self} fraction must be between 0.0 and 1.0"));
+        }
+
+        self.selection().map_or(Ok(()), |s| s.validate())
+    }
+}
+
+// eg. elite=0.1, crossover:roulette=0.6, mutate:tournament(6)=0.1, random=0.2
+impl FromStr for StageConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (op, fraction) = s
+            .rsplit_once('=')
+            .ok_or_else(|| format!("expected op=fraction, got {s:?}"))?;
+        let fraction: f64 = fraction
+            .trim()
+            .parse()
+            .map_err(|_| format!("bad fraction in {s:?}"))?;
+        let (name, selection) = match op.split_once(':') {
+            Some((name, selection)) => (name.trim(), Some(selection.parse()?)),
+            None => (op.trim(), None),
+        };
+
+        let op = match (name, selection) {
+            ("elite", None) => ReproductionKind::Elite,
+            ("mutated_elite", None) => ReproductionKind::MutatedElite,
+            ("crossover", selection) => ReproductionKind::Crossover {
+                selection: selection.unwrap_or(SelectionKind::Roulette),
+            },
+            ("mutate", selection) => ReproductionKind::Mutate {
+                selection: selection.unwrap_or(SelectionKind::Tournament { size: 6 }),
+            },
+            ("random", None) => ReproductionKind::Random,
+            _ => {
+                return Err(format!(
+                    "unknown stage {op:?}, expected elite, mutated_elite, crossover[:selection], mutate[:selection] or random"
+                ))
+            }
+        };
+
+        Ok(Self { fraction, op })
+    }
+}
+
+impl fmt::Display for StageConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.op {
+            ReproductionKind::Elite => write!(f, "elite")?,
+            ReproductionKind::MutatedElite => write!(f, "mutated_elite")?,
+            ReproductionKind::Crossover { selection } => write!(f, "crossover:{selection}")?,
+            ReproductionKind::Mutate { selection } => write!(f, "mutate:{selection}")?,
+            ReproductionKind::Random => write!(f, "random")?,
+        }
+        write!(f, "={}", self.fraction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pipeline(fractions: &[f64]) -> Vec<Stage> {
+        fractions
+            .iter()
+            .map(|&fraction| Stage {
+                fraction,
+                op: Box::new(Elitism),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn stage_counts_add_up_to_num_agents() {
+        let default_fractions: Vec<f64> = StageConfig::default_pipeline()
+            .iter()
+            .map(|stage| stage.fraction)
+            .collect();
+        let fractions = [
+            default_fractions,
+            vec![1.0 / 3.0; 3],
+            vec![0.25, 0.25, 0.5],
+            vec![0.9995, 0.0],
+        ];
+
+        for fractions in &fractions {
+            let pipeline = pipeline(fractions);
+            for num_agents in [1, 7, 13, 99, 500, 1000, 1001] {
+                let counts = stage_counts(&pipeline, num_agents);
+                assert_eq!(
+                    counts.iter().sum::<usize>(),
+                    num_agents,
+                    "{fractions:?} with {num_agents} agents gave {counts:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn stage_counts_match_the_fractions() {
+        let stages = StageConfig::default_pipeline();
+        let pipeline = pipeline(&stages.iter().map(|s| s.fraction).collect::<Vec<_>>());
+        assert_eq!(stage_counts(&pipeline, 1000), [100, 600, 100, 0, 200]);
+    }
+}
@@ -0,0 +1,343 @@
+//! Selection
+//! Strategies that pick parents from a finished generation based on fitness
+
+use std::fmt;
+use std::str::FromStr;
+
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::SimRng;
+
+pub trait SelectionStrategy: Send + Sync {
+    /// Returns `count` parent indices into `fitness`, repeats are allowed
+    fn select(&self, fitness: &[f32], count: usize, rng: &mut SimRng) -> Vec<usize>;
+
+    /// Whether `select` can tell the agents apart by fitness
+    fn can_select(&self, _fitness: &[f32]) -> bool {
+        true
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SelectionKind {
+    Roulette,
+    Tournament { size: usize },
+    Rank { pressure: f64 },
+    Sus,
+    Uniform,
+}
+
+/// Fitness proportionate selection.
+/// Needs a positive fitness, without one it runs a tournament of `FALLBACK_TOURNAMENT`
+pub struct Roulette;
+
+/// Fittest agent among `size` randomly picked ones, repeats are allowed.
+/// Smaller tournaments -> more exploration
+pub struct Tournament {
+    pub size: usize,
+}
+
+/// Linear ranking, the probability depends on the rank instead of the raw fitness.
+/// `pressure` goes from 1.0 (uniform) to 2.0 (the worst agent is never picked)
+pub struct Rank {
+    pub pressure: f64,
+}
+
+/// Stochastic universal sampling, fitness proportionate with evenly spaced pointers
+/// so the picks can't all land on a few lucky agents
+pub struct StochasticUniversal;
+
+/// Every agent is equally likely
+pub struct Uniform;
+
+impl SelectionKind {
+    #[must_use]
+    pub fn build(self) -> Box<dyn SelectionStrategy> {
+        match self {
+            Self::Roulette => Box::new(Roulette),
+            Self::Tournament { size } => Box::new(Tournament { size }),
+            Self::Rank { pressure } => Box::new(Rank { pressure }),
+            Self::Sus => Box::new(StochasticUniversal),
+            Self::Uniform => Box::new(Uniform),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Self::Tournament { size } if *size == 0 => {
+                Err("tournament size must be greater than 0".to_string())
+            }
+            Self::Rank { pressure } if !(1.0..=2.0).contains(pressure) => Err(format!(
+                "rank pressure must be in 1.0..=2.0, got {pressure}"
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl SelectionStrategy for Roulette {
+    fn select(&self, fitness: &[f32], count: usize, rng: &mut SimRng) -> Vec<usize> {
+        match fitness_weights(fitness).map(|weights| WeightedIndex::new(&weights)) {
+            Some(Ok(pool)) => (0..count).map(|_| pool.sample(rng)).collect(),
+            _ => Roulette::fallback().select(fitness, count, rng),
+        }
+    }
+
+    fn can_select(&self, fitness: &[f32]) -> bool {
+        fitness_weights(fitness).is_some()
+    }
+}
+
+impl Roulette {
+    /// The tournament size used when no agent has a usable fitness,
+    /// the original sim picked the best of 6
+    pub const FALLBACK_TOURNAMENT: usize = 6;
+
+    #[must_use]
+    pub fn fallback() -> Tournament {
+        Tournament {
+            size: Self::FALLBACK_TOURNAMENT,
+        }
+    }
+}
+
+impl SelectionStrategy for Tournament {
+    fn select(&self, fitness: &[f32], count: usize, rng: &mut SimRng) -> Vec<usize> {
+        (0..count)
+            .map(|_| {
+                let mut best_idx = rng.gen_range(0..fitness.len());
+                for _ in 1..self.size {
+                    let idx = rng.gen_range(0..fitness.len());
+                    if fitness[idx] > fitness[best_idx] {
+                        best_idx = idx;
+                    }
+                }
+                best_idx
+            })
+            .collect()
+    }
+}
+
+impl SelectionStrategy for Rank {
+    fn select(&self, fitness: &[f32], count: usize, rng: &mut SimRng) -> Vec<usize> {
+        let n = fitness.len();
+        if n < 2 {
+            return Uniform.select(fitness, count, rng);
+        }
+
+        // Worst agent gets rank 0
+        let mut ranked: Vec<usize> = (0..n).collect();
+        ranked.sort_by(|&a, &b| fitness[a].total_cmp(&fitness[b]));
+
+        let s = self.pressure;
+        let weights = (0..n).map(|rank| {
+            (2.0 - s) / n as f64 + 2.0 * rank as f64 * (s - 1.0) / (n * (n - 1)) as f64
+        });
+        match WeightedIndex::new(weights) {
+            Ok(pool) => (0..count).map(|_| ranked[pool.sample(rng)]).collect(),
+            Err(_) => Uniform.select(fitness, count, rng),
+        }
+    }
+}
+
+impl SelectionStrategy for StochasticUniversal {
+    fn select(&self, fitness: &[f32], count: usize, rng: &mut SimRng) -> Vec<usize> {
+        let Some(weights) = fitness_weights(fitness) else {
+            return Uniform.select(fitness, count, rng);
+        };
+        let total: f32 = weights.iter().sum();
+        if count == 0 || total <= 0.0 {
+            return Uniform.select(fitness, count, rng);
+        }
+
+        let spacing = total / count as f32;
+        let mut pointer = rng.gen_range(0.0..spacing);
+        let mut cumulative = 0.0;
+        let mut selected = Vec::with_capacity(count);
+        for (idx, weight) in weights.iter().enumerate() {
+            cumulative += weight;
+            while pointer < cumulative && selected.len() < count {
+                selected.push(idx);
+                pointer += spacing;
+            }
+        }
+
+        // Float rounding can leave the last pointer just past the end
+        while selected.len() < count {
+            selected.push(weights.len() - 1);
+        }
+
+        selected
+    }
+}
+
+impl SelectionStrategy for Uniform {
+    fn select(&self, fitness: &[f32], count: usize, rng: &mut SimRng) -> Vec<usize> {
+        (0..count)
+            .map(|_| rng.gen_range(0..fitness.len()))
+            .collect()
+    }
+}
+
+// Fitness scaled to 0..100, non finite values get no weight
+fn fitness_weights(fitness: &[f32]) -> Option<Vec<f32>> {
+    let max_fitness = fitness
+        .iter()
+        .copied()
+        .filter(|f| f.is_finite())
+        .fold(0.0, f32::max);
+    if max_fitness <= 0.0 {
+        return None;
+    }
+
+    let weights = fitness
+        .iter()
+        .map(|&f| {
+            if f.is_finite() {
+                (f.max(0.0) / max_fitness) * 100.0
+            } else {
+                0.0
+            }
+        })
+        .collect();
+
+    Some(weights)
+}
+
+// eg. roulette, tournament(6), rank(1.5), sus, uniform
+impl FromStr for SelectionKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, param) = match s.split_once('(') {
+            Some((name, rest)) => (name, rest.strip_suffix(')')),
+            None => (s, None),
+        };
+        let parse_err = || format!("bad selection param in {s:?}");
+
+        match name.trim() {
+            "roulette" => Ok(Self::Roulette),
+            "tournament" => Ok(Self::Tournament {
+                size: param.map_or(Ok(6), |p| p.trim().parse().map_err(|_| parse_err()))?,
+            }),
+            "rank" => Ok(Self::Rank {
+                pressure: param.map_or(Ok(1.5), |p| p.trim().parse().map_err(|_| parse_err()))?,
+            }),
+            "sus" => Ok(Self::Sus),
+            "uniform" => Ok(Self::Uniform),
+            _ => Err(format!(
+                "unknown selection {name:?}, expected roulette, tournament(size), rank(pressure), sus or uniform"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SelectionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Roulette => write!(f, "roulette"),
+            Self::Tournament { size } => write!(f, "tournament({size})"),
+            Self::Rank { pressure } => write!(f, "rank({pressure})"),
+            Self::Sus => write!(f, "sus"),
+            Self::Uniform => write!(f, "uniform"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+
+    const SAMPLES: usize = 100_000;
+
+    fn frequencies(strategy: &dyn SelectionStrategy, fitness: &[f32]) -> Vec<f64> {
+        let mut rng = SimRng::seed_from_u64(7);
+        let mut counts = vec![0; fitness.len()];
+        for idx in strategy.select(fitness, SAMPLES, &mut rng) {
+            counts[idx] += 1;
+        }
+        counts.iter().map(|&c| c as f64 / SAMPLES as f64).collect()
+    }
+
+    fn assert_close(actual: &[f64], expected: &[f64]) {
+        for (a, e) in actual.iter().zip(expected) {
+            assert!(
+                (a - e).abs() < 0.01,
+                "got {actual:?}, expected {expected:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn tournament_picks_the_best_of_size_entrants() {
+        let fitness = [3.0, 0.0, 2.0, 1.0];
+        let n = fitness.len() as f64;
+        let entrants = 3;
+
+        // Ranked worst first, the winner is the max of `entrants` uniform picks
+        let expected: Vec<f64> = fitness
+            .iter()
+            .map(|&f| {
+                let rank = f64::from(f);
+                ((rank + 1.0) / n).powi(entrants) - (rank / n).powi(entrants)
+            })
+            .collect();
+        let actual = frequencies(&Tournament { size: 3 }, &fitness);
+        assert_close(&actual, &expected);
+
+        // A single entrant is a uniform pick
+        let actual = frequencies(&Tournament { size: 1 }, &fitness);
+        assert_close(&actual, &[0.25; 4]);
+    }
+
+    #[test]
+    fn rank_follows_the_linear_ranking() {
+        let fitness = [10.0, -5.0, 40.0, 20.0];
+        let n = fitness.len() as f64;
+        let pressure = 1.8;
+
+        // Ranks 1, 0, 3, 2
+        let expected: Vec<f64> = [1.0, 0.0, 3.0, 2.0]
+            .iter()
+            .map(|rank| (2.0 - pressure) / n + 2.0 * rank * (pressure - 1.0) / (n * (n - 1.0)))
+            .collect();
+        let actual = frequencies(&Rank { pressure }, &fitness);
+        assert_close(&actual, &expected);
+    }
+
+    #[test]
+    fn sus_gives_each_agent_its_share() {
+        let fitness = [1.0, 0.0, 3.0, 4.0, f32::NAN];
+        let count = 16;
+        let mut rng = SimRng::seed_from_u64(7);
+
+        for _ in 0..100 {
+            let selected = StochasticUniversal.select(&fitness, count, &mut rng);
+            assert_eq!(selected.len(), count);
+
+            // Shares of 2, 0, 6, 8 and 0 picks
+            let picks: Vec<usize> = (0..fitness.len())
+                .map(|idx| selected.iter().filter(|&&s| s == idx).count())
+                .collect();
+            assert_eq!(picks, [2, 0, 6, 8, 0]);
+        }
+    }
+
+    #[test]
+    fn roulette_without_positive_fitness_runs_a_tournament() {
+        let fitness = [-1.0, -3.0, -2.0];
+        assert!(!Roulette.can_select(&fitness));
+        assert!(Roulette.can_select(&[0.0, 1.0]));
+
+        let mut rng = SimRng::seed_from_u64(7);
+        let roulette = Roulette.select(&fitness, 100, &mut rng);
+        let mut rng = SimRng::seed_from_u64(7);
+        let tournament = Roulette::fallback().select(&fitness, 100, &mut rng);
+        assert_eq!(roulette, tournament);
+    }
+}
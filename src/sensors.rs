@@ -0,0 +1,552 @@
+//! Sensors
+//! Composable inputs for the agent brain, the input layer size is derived from them
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::game::Game;
+use crate::{get_eight_dirs, FourDirs, GameConfig, Point};
+
+pub trait Sensor: Send + Sync {
+    /// Number of inputs the sensor adds
+    fn size(&self) -> usize;
+    /// Appends exactly `size` values to `inputs`
+    fn sense(&self, view: &View, inputs: &mut Vec<f64>);
+}
+
+/// The game as seen by the snake
+pub struct View<'a> {
+    pub game: &'a Game,
+    /// Directions are relative to the snake heading, `Top` is straight ahead
+    pub egocentric: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SensorKind {
+    Vision,
+    WallRays,
+    BodyRays,
+    FoodRays,
+    FoodDelta,
+    Length,
+    Window { size: usize },
+    Grid,
+    HeadDir,
+    TailDir,
+    FloodFill,
+}
+
+/// The original 8 rays of (1 / distance to a wall or the body, food seen)
+pub struct Vision;
+
+/// 8 rays of 1 / distance to the wall, always 0 on wrap around boards
+pub struct WallRays;
+
+/// 8 rays of 1 / distance to the body, 0 when the ray reaches the wall first
+pub struct BodyRays;
+
+/// 8 rays of 1 / distance to the food, 0 when it isn't on the ray
+pub struct FoodRays;
+
+/// Offset from the head to the food, scaled by the board size
+pub struct FoodDelta;
+
+/// Snake length over the max score
+pub struct Length;
+
+/// `size` x `size` cells around the head, 1 for walls and body, -1 for food
+pub struct Window {
+    pub size: usize,
+}
+
+/// The whole board in the same encoding as `Window`, with 0.5 for the head.
+/// It is always in board coordinates, so it only fits boards of the size it was built for
+pub struct Grid {
+    pub width: i32,
+    pub height: i32,
+}
+
+/// For each of the 4 moves, the share of free cells still reachable after
+/// taking it and whether the tail can be reached, so the snake can tell when
+/// a move would trap it
+pub struct FloodFill;
+
+/// One hot head direction
+pub struct HeadDir;
+
+/// One hot direction the tail is moving in
+pub struct TailDir;
+
+struct Ray {
+    wall: Option<i32>,
+    body: Option<i32>,
+    food: Option<i32>,
+}
+
+impl View<'_> {
+    /// Offset given with `Top` as straight ahead into the board frame
+    fn to_board(&self, offset: (i32, i32)) -> (i32, i32) {
+        if self.egocentric {
+            self.game.dir.rotate(offset)
+        } else {
+            offset
+        }
+    }
+
+    /// Offset on the board into the frame the sensors report in
+    fn to_view(&self, offset: (i32, i32)) -> (i32, i32) {
+        if self.egocentric {
+            self.game.dir.to_local(FourDirs::Top).rotate(offset)
+        } else {
+            offset
+        }
+    }
+
+    fn dir(&self, dir: FourDirs) -> FourDirs {
+        if self.egocentric {
+            self.game.dir.to_local(dir)
+        } else {
+            dir
+        }
+    }
+
+    fn rays(&self) -> impl Iterator<Item = Ray> + '_ {
+        get_eight_dirs()
+            .into_iter()
+            .map(|d| self.cast(self.to_board(d)))
+    }
+
+    fn cast(&self, dir: (i32, i32)) -> Ray {
+        let game = self.game;
+        let mut pt = game.head;
+        let mut ray = Ray {
+            wall: None,
+            body: None,
+            food: None,
+        };
+
+        // Rays on wrap around boards stop once they are back at the head
+        for dist in 1..1000 {
+            pt = game.step(pt, dir);
+            if pt == game.head {
+                break;
+            }
+            if game.is_wall(pt) {
+                ray.wall = Some(dist);
+                break;
+            }
+            if ray.body.is_none() && game.is_snake(pt) {
+                ray.body = Some(dist);
+            }
+            if ray.food.is_none() && game.food == pt {
+                ray.food = Some(dist);
+            }
+        }
+
+        ray
+    }
+
+    fn cell(&self, pt: Point) -> f64 {
+        if self.game.is_wall(pt) || self.game.is_snake(pt) {
+            1.0
+        } else if self.game.food == pt {
+            -1.0
+        } else {
+            0.0
+        }
+    }
+}
+
+impl SensorKind {
+    #[must_use]
+    pub fn build(self, config: &GameConfig) -> Box<dyn Sensor> {
+        match self {
+            Self::Vision => Box::new(Vision),
+            Self::WallRays => Box::new(WallRays),
+            Self::BodyRays => Box::new(BodyRays),
+            Self::FoodRays => Box::new(FoodRays),
+            Self::FoodDelta => Box::new(FoodDelta),
+            Self::Length => Box::new(Length),
+            Self::Window { size } => Box::new(Window { size }),
+            Self::Grid => Box::new(Grid {
+                width: config.width,
+                height: config.height,
+            }),
+            Self::HeadDir => Box::new(HeadDir),
+            Self::TailDir => Box::new(TailDir),
+            Self::FloodFill => Box::new(FloodFill),
+        }
+    }
+
+    /// The original 24 inputs
+    #[must_use]
+    pub fn default_suite() -> Vec<Self> {
+        vec![Self::Vision, Self::HeadDir, Self::TailDir]
+    }
+
+    /// Input layer size for a set of sensors
+    #[must_use]
+    pub fn num_inputs(sensors: &[Self], config: &GameConfig) -> usize {
+        sensors.iter().map(|s| s.build(config).size()).sum()
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Self::Window { size } if size % 2 == 0 => {
+                Err(format!("window size must be odd, got {size}"))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Sensor for Vision {
+    fn size(&self) -> usize {
+        8 * 2
+    }
+
+    fn sense(&self, view: &View, inputs: &mut Vec<f64>) {
+        for d in get_eight_dirs() {
+            let (solid, food) = vision_in_dir(view.game, view.game.head, view.to_board(d));
+            inputs.push(f64::from(solid));
+            inputs.push(if food { 1.0 } else { 0.0 });
+        }
+    }
+}
+
+impl Sensor for WallRays {
+    fn size(&self) -> usize {
+        8
+    }
+
+    fn sense(&self, view: &View, inputs: &mut Vec<f64>) {
+        inputs.extend(view.rays().map(|r| inverse_dist(r.wall)));
+    }
+}
+
+impl Sensor for BodyRays {
+    fn size(&self) -> usize {
+        8
+    }
+
+    fn sense(&self, view: &View, inputs: &mut Vec<f64>) {
+        inputs.extend(view.rays().map(|r| inverse_dist(r.body)));
+    }
+}
+
+impl Sensor for FoodRays {
+    fn size(&self) -> usize {
+        8
+    }
+
+    fn sense(&self, view: &View, inputs: &mut Vec<f64>) {
+        inputs.extend(view.rays().map(|r| inverse_dist(r.food)));
+    }
+}
+
+impl Sensor for FoodDelta {
+    fn size(&self) -> usize {
+        2
+    }
+
+    fn sense(&self, view: &View, inputs: &mut Vec<f64>) {
+        let game = view.game;
+        let (dx, dy) = view.to_view((game.food.x - game.head.x, game.food.y - game.head.y));
+        let board_size = f64::from(game.width().max(game.height()));
+        inputs.push(f64::from(dx) / board_size);
+        inputs.push(f64::from(dy) / board_size);
+    }
+}
+
+impl Sensor for Length {
+    fn size(&self) -> usize {
+        1
+    }
+
+    fn sense(&self, view: &View, inputs: &mut Vec<f64>) {
+        inputs.push(view.game.score() as f64 / view.game.max_score().max(1) as f64);
+    }
+}
+
+impl Sensor for Window {
+    fn size(&self) -> usize {
+        self.size * self.size
+    }
+
+    fn sense(&self, view: &View, inputs: &mut Vec<f64>) {
+        let radius = (self.size / 2) as i32;
+        let head = view.game.head;
+        for y in (-radius..=radius).rev() {
+            for x in -radius..=radius {
+                inputs.push(view.cell(view.game.step(head, view.to_board((x, y)))));
+            }
+        }
+    }
+}
+
+impl Sensor for Grid {
+    fn size(&self) -> usize {
+        ((self.width + 1) * (self.height + 1)) as usize
+    }
+
+    fn sense(&self, view: &View, inputs: &mut Vec<f64>) {
+        for y in 0..=self.height {
+            for x in 0..=self.width {
+                let pt = Point::new(x, y);
+                inputs.push(if pt == view.game.head {
+                    0.5
+                } else {
+                    view.cell(pt)
+                });
+            }
+        }
+    }
+}
+
+impl Sensor for HeadDir {
+    fn size(&self) -> usize {
+        4
+    }
+
+    fn sense(&self, view: &View, inputs: &mut Vec<f64>) {
+        inputs.extend(view.dir(view.game.dir).get_one_hot_dir());
+    }
+}
+
+impl Sensor for TailDir {
+    fn size(&self) -> usize {
+        4
+    }
+
+    fn sense(&self, view: &View, inputs: &mut Vec<f64>) {
+        inputs.extend(view.dir(view.game.tail_dir()).get_one_hot_dir());
+    }
+}
+
+impl Sensor for FloodFill {
+    fn size(&self) -> usize {
+        4 * 2
+    }
+
+    fn sense(&self, view: &View, inputs: &mut Vec<f64>) {
+        let game = view.game;
+        let width = game.width() + 1;
+        let idx = |pt: Point| (pt.y * width + pt.x) as usize;
+
+        // Walls, the body and other snakes, the tail moves out of the way on the next step
+        let mut blocked = vec![false; (width * (game.height() + 1)) as usize];
+        for y in 0..=game.height() {
+            for x in 0..width {
+                blocked[idx(Point::new(x, y))] = game.is_wall(Point::new(x, y));
+            }
+        }
+        for pt in game.body[..game.body.len() - 1].iter().chain(game.others()) {
+            blocked[idx(*pt)] = true;
+        }
+        let tail = game.body[game.body.len() - 1];
+        let free_cells = (game.max_score() + 1)
+            .saturating_sub(game.body.len() + game.others().len())
+            .max(1);
+
+        // Cells keep the index of the fill that reached them, fills never
+        // overlap so moves that start in an already filled region reuse its result
+        let mut filled_by: Vec<Option<usize>> = vec![None; blocked.len()];
+        let mut results = Vec::new();
+        let mut queue = Vec::new();
+        for dir in [
+            FourDirs::Left,
+            FourDirs::Right,
+            FourDirs::Bottom,
+            FourDirs::Top,
+        ] {
+            let start = game.step(game.head, view.to_board(dir.value()));
+            if game.is_wall(start) || blocked[idx(start)] {
+                inputs.extend([0.0, 0.0]);
+                continue;
+            }
+            if let Some(fill) = filled_by[idx(start)] {
+                let (reachable, tail_reached) = results[fill];
+                inputs.extend([reachable, tail_reached]);
+                continue;
+            }
+
+            let fill = results.len();
+            let mut reachable = 0;
+            filled_by[idx(start)] = Some(fill);
+            queue.push(start);
+            while let Some(pt) = queue.pop() {
+                reachable += 1;
+                for d in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+                    let next = game.step(pt, d);
+                    if !blocked[idx(next)] && filled_by[idx(next)].is_none() {
+                        filled_by[idx(next)] = Some(fill);
+                        queue.push(next);
+                    }
+                }
+            }
+
+            let result = (
+                f64::from(reachable) / free_cells as f64,
+                if filled_by[idx(tail)] == Some(fill) {
+                    1.0
+                } else {
+                    0.0
+                },
+            );
+            inputs.extend([result.0, result.1]);
+            results.push(result);
+        }
+    }
+}
+
+fn vision_in_dir(game: &Game, st: Point, dir: (i32, i32)) -> (f32, bool) {
+    let mut food = false;
+    let mut temp_pt: Point = st;
+    let mut dist = 0;
+
+    loop {
+        if game.is_wall(temp_pt) || game.is_snake(temp_pt) {
+            break;
+        }
+
+        if game.food == temp_pt {
+            food = true;
+        }
+
+        temp_pt = game.step(temp_pt, dir);
+
+        dist += 1;
+        // On wrap around boards the ray ends when it comes back to the start
+        if dist > 1000 || temp_pt == st {
+            break;
+        }
+    }
+
+    (1.0 / dist as f32, food)
+}
+
+fn inverse_dist(dist: Option<i32>) -> f64 {
+    dist.map_or(0.0, |d| 1.0 / f64::from(d))
+}
+
+// eg. vision, wall_rays, food_delta, window(5), head_dir
+impl FromStr for SensorKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, param) = match s.split_once('(') {
+            Some((name, rest)) => (name, rest.strip_suffix(')')),
+            None => (s, None),
+        };
+
+        match name.trim() {
+            "vision" => Ok(Self::Vision),
+            "wall_rays" => Ok(Self::WallRays),
+            "body_rays" => Ok(Self::BodyRays),
+            "food_rays" => Ok(Self::FoodRays),
+            "food_delta" => Ok(Self::FoodDelta),
+            "length" => Ok(Self::Length),
+            "window" => Ok(Self::Window {
+                size: param.map_or(Ok(5), |p| {
+                    p.trim()
+                        .parse()
+                        .map_err(|_| format!("bad window size in {s:?}"))
+                })?,
+            }),
+            "grid" => Ok(Self::Grid),
+            "head_dir" => Ok(Self::HeadDir),
+            "tail_dir" => Ok(Self::TailDir),
+            "flood_fill" => Ok(Self::FloodFill),
+            _ => Err(format!(
+                "unknown sensor {name:?}, expected vision, wall_rays, body_rays, food_rays, \
+                food_delta, length, window(size), grid, head_dir, tail_dir or flood_fill"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for SensorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Vision => write!(f, "vision"),
+            Self::WallRays => write!(f, "wall_rays"),
+            Self::BodyRays => write!(f, "body_rays"),
+            Self::FoodRays => write!(f, "food_rays"),
+            Self::FoodDelta => write!(f, "food_delta"),
+            Self::Length => write!(f, "length"),
+            Self::Window { size } => write!(f, "window({size})"),
+            Self::Grid => write!(f, "grid"),
+            Self::HeadDir => write!(f, "head_dir"),
+            Self::TailDir => write!(f, "tail_dir"),
+            Self::FloodFill => write!(f, "flood_fill"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::GameRecord;
+
+    // A 5x5 board split by the snake, heading left along row 2 with its tail
+    // in the top part
+    fn split_board() -> Game {
+        let config = GameConfig {
+            width: 6,
+            height: 6,
+            ..Default::default()
+        };
+        let mut start_body: Vec<Point> = (1..=5).map(|x| Point::new(x, 2)).collect();
+        start_body.push(Point::new(5, 3));
+
+        Game::from_record(
+            &config,
+            None,
+            &GameRecord {
+                start_body,
+                start_dir: FourDirs::Left,
+                foods: vec![Point::new(5, 5)],
+                moves: Vec::new(),
+            },
+        )
+    }
+
+    fn flood_fill(game: &Game, egocentric: bool) -> Vec<f64> {
+        let mut inputs = Vec::new();
+        FloodFill.sense(&View { game, egocentric }, &mut inputs);
+        assert_eq!(inputs.len(), FloodFill.size());
+        inputs
+    }
+
+    #[test]
+    fn flood_fill_sees_the_trapped_side() {
+        let game = split_board();
+
+        // 20 free cells counting the tail, the bottom row has 5 of them
+        // and the top part the other 15 along with the tail
+        let expected = [0.0, 0.0, 0.0, 0.0, 0.25, 0.0, 0.75, 1.0];
+        assert_eq!(flood_fill(&game, false), expected);
+    }
+
+    #[test]
+    fn egocentric_flood_fill_turns_with_the_snake() {
+        let game = split_board();
+
+        // Heading left, a left turn goes down and a right turn goes up
+        let expected = [0.25, 0.0, 0.75, 1.0, 0.0, 0.0, 0.0, 0.0];
+        assert_eq!(flood_fill(&game, true), expected);
+    }
+
+    #[test]
+    fn flood_fill_leaves_other_snakes_out_of_the_free_cells() {
+        let mut game = split_board();
+        game.set_others(vec![Point::new(4, 1), Point::new(5, 1)]);
+
+        // 18 free cells without the other snake, which takes 2 of the bottom row
+        let expected = [0.0, 0.0, 0.0, 0.0, 3.0 / 18.0, 0.0, 15.0 / 18.0, 1.0];
+        assert_eq!(flood_fill(&game, false), expected);
+    }
+}
@@ -2,54 +2,195 @@
 //! Manages the evolution of population over multiple generations
 
 use std::io;
+use std::sync::Arc;
 use std::time::Instant;
 
+use serde::{Deserialize, Serialize};
+
+use crate::checkpoint::Checkpoint;
+use crate::curriculum::{Curriculum, CurriculumState};
+use crate::fitness::FitnessFn;
+use crate::game::OutcomeCounts;
+use crate::metrics::{GenerationMetrics, MetricsLog};
+use crate::nn::Net;
 use crate::pop::Population;
+use crate::replay::Replay;
 use crate::viz::Viz;
+use crate::SimConfig;
 
 pub struct Simulation {
     gen_count: usize,
     pop: Population,
-    viz: Viz,
+    viz: Option<Viz>,
+    metrics: Option<MetricsLog>,
     gen_start_ts: Instant,
+    sim_start_ts: Instant,
     max_score: usize,
+    best_net: Option<Net>,
+    // Last few summaries, kept for the viz graphs
+    history: Vec<GenerationSummary>,
+    curriculum: Curriculum,
+    config: SimConfig,
 }
 
-#[derive(Default, Clone, Copy)]
+#[derive(Default, Clone, Copy, Serialize, Deserialize)]
 pub struct GenerationSummary {
     pub gen_count: usize,
     pub time_elapsed_secs: f32,
     pub gen_max_score: usize,
     pub sim_max_score: usize,
+    #[serde(default)]
+    pub board_width: i32,
+    #[serde(default)]
+    pub board_height: i32,
+    /// `None` when the run has no curriculum
+    #[serde(default)]
+    pub curriculum_stage: Option<usize>,
+    #[serde(default)]
+    pub outcomes: OutcomeCounts,
 }
 
 impl Simulation {
-    pub fn new() -> io::Result<Self> {
+    /// Headless simulations never touch the terminal,
+    /// progress is printed to stdout once per generation instead
+    pub fn new(config: &SimConfig, is_headless: bool) -> io::Result<Self> {
+        let mut config = config.clone();
+        let seed = *config.sim.seed.get_or_insert_with(rand::random);
+
+        let curriculum = Curriculum::new(&config, CurriculumState::default());
+        let stage_config = curriculum.stage_config(&config);
+        let pop = Population::new(&stage_config, seed)?;
+        let viz = Self::create_viz(&stage_config, is_headless)?;
+        let metrics = Self::open_metrics(&config, None)?;
+
         Ok(Self {
             gen_count: 0,
-            pop: Population::new(),
-            viz: Viz::new()?,
+            pop,
+            viz,
+            metrics,
             gen_start_ts: Instant::now(),
+            sim_start_ts: Instant::now(),
             max_score: 0,
+            best_net: None,
+            history: Vec::new(),
+            curriculum,
+            config,
+        })
+    }
+
+    /// Continues from the start of the generation the checkpoint was taken in
+    pub fn resume(checkpoint: Checkpoint, is_headless: bool) -> io::Result<Self> {
+        let Checkpoint {
+            mut config,
+            pop,
+            max_score,
+            best_net,
+            history,
+            curriculum,
+        } = checkpoint;
+        let seed = *config.sim.seed.get_or_insert_with(rand::random);
+
+        let gen_count = pop.gen_count;
+        let curriculum = Curriculum::new(&config, curriculum);
+        let stage_config = curriculum.stage_config(&config);
+        let pop = Population::from_state(&stage_config, seed, pop);
+        let mut viz = Self::create_viz(&stage_config, is_headless)?;
+        if let Some(viz) = viz.as_mut() {
+            for stats in &history {
+                viz.update_summary(*stats, pop.mutation_rate, pop.mutation_magnitude);
+            }
+            if let Some(net) = &best_net {
+                viz.update_brain(net.clone());
+            }
+        }
+
+        let metrics = Self::open_metrics(&config, Some(gen_count))?;
+
+        Ok(Self {
+            gen_count,
+            pop,
+            viz,
+            metrics,
+            gen_start_ts: Instant::now(),
+            sim_start_ts: Instant::now(),
+            max_score,
+            best_net,
+            history,
+            curriculum,
+            config,
         })
     }
 
+    /// Use a custom fitness function instead of the one picked by the config
+    pub fn set_fitness_fn(&mut self, fitness_fn: Arc<dyn FitnessFn>) {
+        if let Some(viz) = self.viz.as_mut() {
+            viz.set_fitness_fn(fitness_fn.clone());
+        }
+        self.pop.set_fitness_fn(fitness_fn);
+    }
+
     pub fn stop(&self) -> io::Result<()> {
-        Viz::restore_terminal()
+        if self.viz.is_some() {
+            Viz::restore_terminal()?;
+        }
+
+        if self.config.data.checkpoint_every > 0 {
+            self.save_checkpoint()?;
+        }
+
+        Ok(())
     }
 
-    pub fn update(&mut self) {
+    #[must_use]
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            config: self.config.clone(),
+            pop: self.pop.state(),
+            max_score: self.max_score,
+            best_net: self.best_net.clone(),
+            history: self.history.clone(),
+            curriculum: self.curriculum.state(),
+        }
+    }
+
+    pub fn save_checkpoint(&self) -> io::Result<()> {
+        self.checkpoint()
+            .save(&self.config.data.checkpoint_file_name)
+    }
+
+    #[must_use]
+    pub fn gen_count(&self) -> usize {
+        self.gen_count
+    }
+
+    /// `None` for headless runs
+    pub fn viz_mut(&mut self) -> Option<&mut Viz> {
+        self.viz.as_mut()
+    }
+
+    pub fn update(&mut self) -> io::Result<()> {
         let games_alive = self.pop.update();
         if games_alive == 0 {
-            self.end_current_genration();
+            self.end_current_genration()?;
             self.start_new_generation();
+
+            let every = self.config.data.checkpoint_every;
+            if every > 0 && self.gen_count % every == 0 {
+                self.save_checkpoint()?;
+            }
+        }
+
+        if let Some(viz) = self.viz.as_mut() {
+            viz.update();
         }
 
-        self.viz.update();
+        Ok(())
     }
 
     pub fn draw(&mut self) {
-        self.viz.draw();
+        if let Some(viz) = self.viz.as_mut() {
+            viz.draw();
+        }
     }
 
     pub fn start_new_generation(&mut self) {
@@ -57,22 +198,121 @@ impl Simulation {
         self.pop.reset();
     }
 
-    pub fn end_current_genration(&mut self) {
+    pub fn end_current_genration(&mut self) -> io::Result<()> {
         let (best_net, gen_max_score) = self.pop.get_gen_summary();
         if gen_max_score > self.max_score {
             self.max_score = gen_max_score;
-            best_net.save();
-            self.viz.update_brain(best_net);
+            if self.config.data.is_save_best_net {
+                let path = &self.config.data.save_file_name;
+                best_net.save(path)?;
+                self.config.save(&SimConfig::path_for_net(path))?;
+            }
+            if let Some(viz) = self.viz.as_mut() {
+                viz.update_brain(best_net.clone());
+            }
+            self.best_net = Some(best_net);
         }
 
+        let (board_width, board_height) = self.pop.board_size();
         let stats = GenerationSummary {
             gen_count: self.gen_count,
             time_elapsed_secs: self.gen_start_ts.elapsed().as_secs_f32(),
             gen_max_score,
             sim_max_score: self.max_score,
+            board_width,
+            board_height,
+            curriculum_stage: self.curriculum.stage(),
+            outcomes: self.pop.outcome_counts(),
         };
-        self.viz
-            .update_summary(stats, self.pop.mutation_rate, self.pop.mutation_magnitude);
+        if let Some(dir) = &self.config.data.replay_dir {
+            let game = &self.pop.best_agent().game;
+            Replay::from_game(game, Some(self.gen_count))
+                .save(&format!("{dir}/gen-{}.json", self.gen_count))?;
+        }
+        if let Some(metrics) = self.metrics.as_mut() {
+            metrics.write(&GenerationMetrics::new(
+                &stats,
+                self.pop.agents(),
+                &self.pop.fitness(),
+                self.pop.mutation_rate,
+                self.pop.mutation_magnitude,
+            ))?;
+        }
+
+        self.history.push(stats);
+        if self.history.len() > self.config.viz.graphs_len {
+            self.history.remove(0);
+        }
+        match self.viz.as_mut() {
+            Some(viz) => {
+                viz.update_summary(stats, self.pop.mutation_rate, self.pop.mutation_magnitude);
+            }
+            None => self.print_summary(&stats),
+        }
         self.gen_start_ts = Instant::now();
+        self.advance_curriculum(gen_max_score);
+
+        Ok(())
+    }
+
+    // The new board is used from the next generation on, scores on it
+    // aren't comparable so the best score and net start over
+    fn advance_curriculum(&mut self, gen_max_score: usize) {
+        let Some(stage) = self.curriculum.advance(self.gen_count, gen_max_score) else {
+            return;
+        };
+
+        self.pop.set_board(stage.width, stage.height);
+        self.max_score = 0;
+        self.best_net = None;
+        match self.viz.as_mut() {
+            Some(viz) => viz.set_board(stage.width, stage.height),
+            None => println!(
+                "Curriculum: stage {}/{}, board {}x{}",
+                self.curriculum.state().stage + 1,
+                self.curriculum.num_stages(),
+                stage.width,
+                stage.height
+            ),
+        }
+    }
+
+    fn create_viz(config: &SimConfig, is_headless: bool) -> io::Result<Option<Viz>> {
+        if is_headless {
+            println!("Seed: {}", config.sim.seed.unwrap_or_default());
+            return Ok(None);
+        }
+
+        Ok(Some(Viz::new(config)?))
+    }
+
+    /// Resumed runs drop the rows they are about to log again
+    fn open_metrics(
+        config: &SimConfig,
+        resume_gen: Option<usize>,
+    ) -> io::Result<Option<MetricsLog>> {
+        let Some(path) = config.data.metrics_file_name.as_deref() else {
+            return Ok(None);
+        };
+        match resume_gen {
+            Some(gen_count) => MetricsLog::resume(path, gen_count).map(Some),
+            None => MetricsLog::open(path).map(Some),
+        }
+    }
+
+    fn print_summary(&self, stats: &GenerationSummary) {
+        let max_score = self.pop.max_score();
+        println!(
+            "Gen: {}, Max: {}/{}, Gen_Max: {}/{}, Ts: {:.2}, Sim_Ts: {:.2}, MR: {:.2}, MG: {:.2}",
+            stats.gen_count,
+            stats.sim_max_score,
+            max_score,
+            stats.gen_max_score,
+            max_score,
+            stats.time_elapsed_secs,
+            self.sim_start_ts.elapsed().as_secs_f32() / 60.0,
+            self.pop.mutation_rate,
+            self.pop.mutation_magnitude
+        );
     }
 }
@@ -1,14 +1,28 @@
-use rand::Rng;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
 
-use crate::GRID_SIZE;
+/// Rng used by everything that affects a run, it is portable across platforms
+/// so a seed reproduces the same run anywhere
+pub type SimRng = ChaCha8Rng;
 
-#[derive(Default, PartialEq, Eq, Hash, Clone, Copy, Debug)]
+/// Independent rng streams derived from the run seed
+#[derive(Clone, Copy)]
+pub enum RngStream {
+    Game,
+    Population,
+    Eval,
+    Versus,
+}
+
+#[derive(Default, PartialEq, Eq, Hash, Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct Point {
     pub x: i32,
     pub y: i32,
 }
 
-#[derive(Debug, Clone, Copy, Default, PartialEq)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum FourDirs {
     #[default]
     Left,
@@ -31,10 +45,28 @@ pub fn get_eight_dirs() -> [(i32, i32); 8] {
     ]
 }
 
+/// Seeds are derived from the run seed and the position of the rng in the run,
+/// eg. the generation and agent index, so they don't depend on thread scheduling
+#[must_use]
+pub fn seeded_rng(seed: u64, stream: RngStream, keys: &[u64]) -> SimRng {
+    let seed = keys
+        .iter()
+        .fold(splitmix64(seed ^ stream as u64), |acc, &key| {
+            splitmix64(acc ^ key)
+        });
+    SimRng::seed_from_u64(seed)
+}
+
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
 impl FourDirs {
     #[must_use]
-    pub fn get_rand_dir() -> Self {
-        let mut rng = rand::thread_rng();
+    pub fn get_rand_dir(rng: &mut impl Rng) -> Self {
         match rng.gen_range(0..4) {
             0 => Self::Left,
             1 => Self::Right,
@@ -53,6 +85,52 @@ impl FourDirs {
         }
     }
 
+    #[must_use]
+    pub fn turn_left(&self) -> Self {
+        Self::from_quarter_turns(self.quarter_turns() + 3)
+    }
+
+    #[must_use]
+    pub fn turn_right(&self) -> Self {
+        Self::from_quarter_turns(self.quarter_turns() + 1)
+    }
+
+    #[must_use]
+    pub fn opposite(&self) -> Self {
+        Self::from_quarter_turns(self.quarter_turns() + 2)
+    }
+
+    /// `dir` as seen by a snake heading in `self`, where `Top` is straight ahead
+    #[must_use]
+    pub fn to_local(&self, dir: Self) -> Self {
+        Self::from_quarter_turns(dir.quarter_turns() + 4 - self.quarter_turns())
+    }
+
+    /// Rotates an offset given with `Top` as straight ahead into the world frame
+    #[must_use]
+    pub fn rotate(&self, offset: (i32, i32)) -> (i32, i32) {
+        (0..self.quarter_turns()).fold(offset, |(x, y), _| (y, -x))
+    }
+
+    // Clockwise quarter turns from Top
+    fn quarter_turns(&self) -> u8 {
+        match self {
+            Self::Top => 0,
+            Self::Right => 1,
+            Self::Bottom => 2,
+            Self::Left => 3,
+        }
+    }
+
+    fn from_quarter_turns(turns: u8) -> Self {
+        match turns % 4 {
+            0 => Self::Top,
+            1 => Self::Right,
+            2 => Self::Bottom,
+            _ => Self::Left,
+        }
+    }
+
     #[must_use]
     pub fn get_one_hot_dir(&self) -> Vec<f64> {
         match self {
@@ -74,15 +152,6 @@ impl Point {
     pub fn equals(&self, other: Self) -> bool {
         self.x == other.x && self.y == other.y
     }
-
-    #[must_use]
-    pub fn rand() -> Self {
-        let mut rng = rand::thread_rng();
-        Self {
-            x: rng.gen_range(1..GRID_SIZE - 1),
-            y: rng.gen_range(1..GRID_SIZE - 1),
-        }
-    }
 }
 
 // Tuple to point
@@ -99,3 +168,50 @@ impl From<Point> for (i32, i32) {
         (point.x, point.y)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL_DIRS: [FourDirs; 4] = [
+        FourDirs::Left,
+        FourDirs::Right,
+        FourDirs::Bottom,
+        FourDirs::Top,
+    ];
+
+    #[test]
+    fn rotate_undoes_to_local() {
+        for heading in ALL_DIRS {
+            for dir in ALL_DIRS {
+                let local = heading.to_local(dir);
+                assert_eq!(heading.rotate(local.value()), dir.value());
+            }
+        }
+    }
+
+    #[test]
+    fn heading_is_straight_ahead() {
+        for heading in ALL_DIRS {
+            assert_eq!(heading.to_local(heading), FourDirs::Top);
+            assert_eq!(heading.to_local(heading.opposite()), FourDirs::Bottom);
+            assert_eq!(heading.to_local(heading.turn_left()), FourDirs::Left);
+            assert_eq!(heading.to_local(heading.turn_right()), FourDirs::Right);
+            assert_eq!(heading.rotate(FourDirs::Top.value()), heading.value());
+        }
+    }
+
+    #[test]
+    fn rotations_compose() {
+        let offsets = [(1, 0), (2, -1), (-3, 5), (0, 0)];
+        for heading in ALL_DIRS {
+            // Back to the world frame through the inverse rotation
+            let inverse = heading.to_local(FourDirs::Top);
+            for offset in offsets {
+                assert_eq!(inverse.rotate(heading.rotate(offset)), offset);
+            }
+            assert_eq!(heading.turn_left().turn_right(), heading);
+            assert_eq!(heading.opposite().opposite(), heading);
+        }
+    }
+}
@@ -1,8 +1,10 @@
 //! Visualization
 //! Responsible for rendering the game state and neural network on the terminal
 
+use std::cmp::Ordering;
 use std::io::{self, stdout, Stdout};
-use std::time::Instant;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use crossterm::terminal::{
     disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
@@ -12,25 +14,36 @@ use ratatui::backend::CrosstermBackend;
 use ratatui::prelude::*;
 use ratatui::widgets::canvas::{Canvas, Painter, Shape};
 use ratatui::widgets::{
-    Block, BorderType, Borders, Gauge, List, ListItem, Padding, Paragraph, Sparkline,
+    Bar, BarChart, BarGroup, Block, BorderType, Borders, Gauge, List, ListItem, Padding, Paragraph,
+    Sparkline, Wrap,
 };
 use symbols::Marker;
 
-use crate::agent::Agent;
-use crate::game::Game;
+use crate::agent::{ActionSpace, Agent};
+use crate::arena::Arena;
+use crate::fitness::FitnessFn;
+use crate::game::{Game, OutcomeCounts};
 use crate::nn::Net;
+use crate::replay::{Playback, Seek};
+use crate::sensors::SensorKind;
 use crate::sim::GenerationSummary;
-use crate::{
-    FourDirs, Point, GRID_SIZE, IS_LOAD_SAVED_DATA, IS_LOW_DETAIL_MODE, IS_SAVE_BEST_NET, NN_ARCH,
-    NUM_AGENTS, NUM_STEPS, USE_GAME_CANVAS, VIZ_GAME_SCALE, VIZ_GRAPHS_LEN, VIZ_OFFSET,
-    VIZ_UPDATE_FRAMES,
-};
+use crate::{FourDirs, Point, SimConfig, VizConfig};
 
 const COLOR_WALLS: Color = Color::Indexed(137);
 const COLOR_BODY: Color = Color::Indexed(140);
 const COLOR_HEAD: Color = Color::White;
 const COLOR_DEAD: Color = Color::Indexed(205);
 const COLOR_FOOD: Color = Color::LightGreen;
+/// Arena snakes, in the order of their nets
+const SNAKE_COLORS: [Color; 7] = [
+    COLOR_BODY,
+    Color::LightCyan,
+    Color::LightYellow,
+    Color::LightRed,
+    Color::LightBlue,
+    Color::Indexed(208),
+    Color::Indexed(44),
+];
 
 pub struct Viz {
     frame_count: u32,
@@ -38,10 +51,37 @@ pub struct Viz {
     term: Terminal<CrosstermBackend<Stdout>>,
 }
 
+/// Terminal ui of the replay command
+pub struct ReplayViz {
+    config: VizConfig,
+    term: Terminal<CrosstermBackend<Stdout>>,
+}
+
+/// Terminal ui of the play and versus commands
+pub struct PlayViz {
+    config: VizConfig,
+    tick: Duration,
+    term: Terminal<CrosstermBackend<Stdout>>,
+}
+
+/// Terminal ui of the arena command
+pub struct ArenaViz {
+    term: Terminal<CrosstermBackend<Stdout>>,
+}
+
+/// Finished versus games
+#[derive(Clone, Copy, Debug, Default)]
+pub struct VersusTally {
+    pub human: usize,
+    pub net: usize,
+    pub draws: usize,
+}
+
 struct TermViz;
 
 struct GameRender<'a> {
     game: &'a Game,
+    config: &'a VizConfig,
 }
 
 struct NNColors {
@@ -52,8 +92,17 @@ struct NNColors {
     out_colors: Vec<Color>,
 }
 
+/// The paused watched game, `agent` plays the selected step
+struct Scrubber {
+    playback: Playback,
+    agent: Agent,
+}
+
 struct VizData {
+    config: SimConfig,
+    fitness_fn: Arc<dyn FitnessFn>,
     agent: Option<Agent>,
+    scrubber: Option<Scrubber>,
     stats: GenerationSummary,
     sim_start_ts: Instant,
     scores: Vec<u64>,
@@ -63,16 +112,34 @@ struct VizData {
 }
 
 impl Viz {
-    pub fn new() -> io::Result<Self> {
+    pub fn new(config: &SimConfig) -> io::Result<Self> {
         Ok(Self {
             frame_count: 0,
-            data: VizData::default(),
+            data: VizData::new(config),
             term: TermViz::init_terminal()?,
         })
     }
 
     pub fn update_brain(&mut self, new_brain: Net) {
-        self.data.agent = Some(Agent::with_brain(new_brain));
+        self.data.agent = Some(Agent::with_brain(
+            new_brain,
+            &self.data.config,
+            rand::random(),
+        ));
+    }
+
+    /// Restarts the watched agent on the new board
+    pub fn set_board(&mut self, width: i32, height: i32) {
+        self.data.config.game.width = width;
+        self.data.config.game.height = height;
+        self.data.scrubber = None;
+        if let Some(agent) = self.data.agent.take() {
+            self.update_brain(agent.brain);
+        }
+    }
+
+    pub fn set_fitness_fn(&mut self, fitness_fn: Arc<dyn FitnessFn>) {
+        self.data.fitness_fn = fitness_fn;
     }
 
     pub fn update_summary(&mut self, stats: GenerationSummary, mr: f64, mg: f64) {
@@ -84,21 +151,50 @@ impl Viz {
         self.data
             .gen_times
             .push((stats.time_elapsed_secs * 1000.0) as u64);
-        if self.data.scores.len() > VIZ_GRAPHS_LEN {
+        let graphs_len = self.data.config.viz.graphs_len;
+        if self.data.scores.len() > graphs_len {
             self.data.scores.remove(0);
         }
-        if self.data.gen_times.len() > VIZ_GRAPHS_LEN {
+        if self.data.gen_times.len() > graphs_len {
             self.data.gen_times.remove(0);
         }
     }
 
+    /// Pauses the watched game to step through it, or resumes it
+    pub fn toggle_pause(&mut self) {
+        if self.data.scrubber.take().is_some() {
+            return;
+        }
+
+        if let Some(agent) = &self.data.agent {
+            let mut playback = Playback::from_game(&agent.game);
+            playback.seek(Seek::End);
+            self.data.scrubber = Some(Scrubber {
+                agent: agent.clone(),
+                playback,
+            });
+        }
+    }
+
+    /// Moves through the watched game, pausing it first
+    pub fn seek(&mut self, seek: Seek) {
+        if self.data.scrubber.is_none() {
+            self.toggle_pause();
+        }
+
+        if let Some(scrubber) = self.data.scrubber.as_mut() {
+            scrubber.playback.seek(seek);
+            scrubber.agent.game = scrubber.playback.game().clone();
+        }
+    }
+
     pub fn update(&mut self) {
-        if self.data.agent.is_none() {
+        if self.data.agent.is_none() || self.data.scrubber.is_some() {
             return;
         }
 
         self.frame_count = (self.frame_count + 1) % 1000;
-        if self.frame_count % VIZ_UPDATE_FRAMES != 0 {
+        if self.frame_count % self.data.config.viz.update_frames != 0 {
             return;
         }
 
@@ -107,7 +203,11 @@ impl Viz {
         let agent = self.data.agent.as_mut().unwrap();
         let is_alive = agent.update();
         if !is_alive {
-            self.data.agent = Some(Agent::with_brain(agent.brain.clone()));
+            self.data.agent = Some(Agent::with_brain(
+                agent.brain.clone(),
+                &self.data.config,
+                rand::random(),
+            ));
         }
     }
 
@@ -122,6 +222,79 @@ impl Viz {
     }
 }
 
+impl ReplayViz {
+    pub fn new(config: &VizConfig) -> io::Result<Self> {
+        Ok(Self {
+            config: config.clone(),
+            term: TermViz::init_terminal()?,
+        })
+    }
+
+    pub fn draw(&mut self, playback: &Playback, is_paused: bool, steps_per_sec: u32) {
+        let _ = self.term.draw(|f| {
+            TermViz::draw_replay(f, &self.config, playback, is_paused, steps_per_sec);
+        });
+    }
+}
+
+impl PlayViz {
+    pub fn new(config: &VizConfig, tick: Duration) -> io::Result<Self> {
+        Ok(Self {
+            config: config.clone(),
+            tick,
+            term: TermViz::init_terminal()?,
+        })
+    }
+
+    /// Board directions for screen up, down, left and right. The block
+    /// renderer draws rows along x, the canvas draws them along y
+    #[must_use]
+    pub fn screen_dirs(&self) -> [FourDirs; 4] {
+        if self.config.use_game_canvas {
+            [
+                FourDirs::Bottom,
+                FourDirs::Top,
+                FourDirs::Left,
+                FourDirs::Right,
+            ]
+        } else {
+            [
+                FourDirs::Left,
+                FourDirs::Right,
+                FourDirs::Bottom,
+                FourDirs::Top,
+            ]
+        }
+    }
+
+    pub fn draw(&mut self, game: &Game, game_idx: usize, best_score: usize) {
+        let _ = self.term.draw(|f| {
+            TermViz::draw_play(f, &self.config, game, game_idx, best_score, self.tick);
+        });
+    }
+
+    pub fn draw_versus(&mut self, human: &Game, net: &Game, game_idx: usize, tally: VersusTally) {
+        let _ = self.term.draw(|f| {
+            TermViz::draw_versus(f, &self.config, human, net, game_idx, tally, self.tick);
+        });
+    }
+}
+
+impl ArenaViz {
+    pub fn new() -> io::Result<Self> {
+        Ok(Self {
+            term: TermViz::init_terminal()?,
+        })
+    }
+
+    /// `names` and `wins` are per snake
+    pub fn draw(&mut self, arena: &Arena, names: &[String], wins: &[usize], round: usize) {
+        let _ = self.term.draw(|f| {
+            TermViz::draw_arena(f, arena, names, wins, round);
+        });
+    }
+}
+
 // Handles rataui terminal rendering
 impl TermViz {
     fn init_terminal() -> io::Result<Terminal<CrosstermBackend<Stdout>>> {
@@ -140,7 +313,7 @@ impl TermViz {
             return;
         }
 
-        if IS_LOW_DETAIL_MODE {
+        if viz.config.viz.is_low_detail_mode {
             f.render_widget(
                 TermViz::widget_raw_text(TermViz::get_simple_render_text(viz)),
                 f.size(),
@@ -148,7 +321,7 @@ impl TermViz {
             return;
         }
 
-        let agent = viz.agent.as_ref().unwrap();
+        let agent = viz.watched_agent().unwrap();
         let root = Layout::horizontal([
             Constraint::Percentage(35),
             Constraint::Percentage(40),
@@ -156,8 +329,9 @@ impl TermViz {
         ]);
         let net_viz_vertical =
             Layout::vertical([Constraint::Percentage(75), Constraint::Percentage(25)]);
+        let timeline_height = if viz.scrubber.is_some() { 5 } else { 0 };
         let game_viz_vertical =
-            Layout::vertical([Constraint::Percentage(100), Constraint::Percentage(0)]);
+            Layout::vertical([Constraint::Min(0), Constraint::Length(timeline_height)]);
         let stats_viz_vertical = Layout::vertical([
             Constraint::Percentage(25),
             Constraint::Percentage(15),
@@ -168,18 +342,23 @@ impl TermViz {
         ]);
 
         let [game_lane, net_lane, stats_lane] = root.areas(f.size());
-        let [nn_viz_area, about_area] = net_viz_vertical.areas(net_lane);
-        let [game_area, _] = game_viz_vertical.areas(game_lane);
+        let net_bottom_horizontal =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)]);
+        let [nn_viz_area, net_bottom] = net_viz_vertical.areas(net_lane);
+        let [about_area, outcomes_area] = net_bottom_horizontal.areas(net_bottom);
+        let [game_area, timeline_area] = game_viz_vertical.areas(game_lane);
         let [sim_summary, viz_summary, viz_score_gauge, max_score_gauge, gen_times_graph, score_graph] =
             stats_viz_vertical.areas(stats_lane);
 
-        f.render_widget(TermViz::render_about(), about_area);
+        let max_score = agent.game.max_score();
+        f.render_widget(TermViz::render_about(&viz.config), about_area);
+        f.render_widget(TermViz::render_outcomes(&viz.stats.outcomes), outcomes_area);
         f.render_widget(
-            TermViz::render_viz_score_gauge(agent.game.score()),
+            TermViz::render_viz_score_gauge(agent.game.score(), max_score),
             viz_score_gauge,
         );
         f.render_widget(
-            TermViz::render_max_score_gauge(viz.stats.sim_max_score),
+            TermViz::render_max_score_gauge(viz.stats.sim_max_score, max_score),
             max_score_gauge,
         );
         f.render_widget(TermViz::render_score_graph(&viz.scores), score_graph);
@@ -193,38 +372,329 @@ impl TermViz {
                 &viz.sim_start_ts,
                 viz.mutation_rate,
                 viz.mutation_magnitude,
+                max_score,
+                viz.config.sim.curriculum.len(),
             ),
             sim_summary,
         );
-        f.render_widget(TermViz::render_viz_stats(agent), viz_summary);
+        f.render_widget(
+            TermViz::render_viz_stats(agent, viz.fitness_fn.fitness(&agent.game), max_score),
+            viz_summary,
+        );
         f.render_widget(TermViz::render_nn(agent), nn_viz_area);
 
-        if USE_GAME_CANVAS {
-            f.render_widget(TermViz::render_game_canvas(&agent.game), game_area);
+        if viz.config.viz.use_game_canvas {
+            f.render_widget(
+                TermViz::render_game_canvas(&agent.game, &viz.config.viz),
+                game_area,
+            );
         } else {
             f.render_widget(TermViz::display_game_blocks(&agent.game), game_area);
         }
+        if let Some(scrubber) = &viz.scrubber {
+            f.render_widget(
+                TermViz::render_timeline(&scrubber.playback, timeline_area.width),
+                timeline_area,
+            );
+        }
+    }
+
+    fn draw_replay(
+        f: &mut Frame,
+        config: &VizConfig,
+        playback: &Playback,
+        is_paused: bool,
+        steps_per_sec: u32,
+    ) {
+        let root = Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)]);
+        let stats_vertical =
+            Layout::vertical([Constraint::Percentage(60), Constraint::Percentage(40)]);
+        let game_vertical = Layout::vertical([Constraint::Min(0), Constraint::Length(5)]);
+        let [game_lane, stats_lane] = root.areas(f.size());
+        let [game_area, timeline_area] = game_vertical.areas(game_lane);
+        let [stats_area, controls_area] = stats_vertical.areas(stats_lane);
+
+        let game = playback.game();
+        if config.use_game_canvas {
+            f.render_widget(TermViz::render_game_canvas(game, config), game_area);
+        } else {
+            f.render_widget(TermViz::display_game_blocks(game), game_area);
+        }
+        f.render_widget(
+            TermViz::render_timeline(playback, timeline_area.width),
+            timeline_area,
+        );
+
+        let replay = playback.replay();
+        let mut items = vec![
+            format!("Step: {}/{}", playback.step(), playback.num_steps()),
+            format!("Score: {}/{}", game.score(), game.max_score()),
+            format!("Outcome: {:?}", game.outcome),
+        ];
+        if let Some(gen_count) = replay.gen_count {
+            items.push(format!("Gen: {gen_count}"));
+        }
+        if let Some((name, _)) = &replay.level {
+            items.push(format!("Level: {name}"));
+        }
+        items.push(format!("Board: {}x{}", game.width(), game.height()));
+        items.push(if is_paused {
+            "Paused".to_string()
+        } else {
+            format!("Speed: {steps_per_sec} steps/sec")
+        });
+        f.render_widget(
+            TermViz::widget_stats_block("  R E P L A Y  ", items),
+            stats_area,
+        );
+
+        let controls = vec![
+            "[Space] Play / Pause".to_string(),
+            "[Left/Right] Step".to_string(),
+            "[Home/End] Jump".to_string(),
+            "[+/-] Speed".to_string(),
+            "[R] Restart".to_string(),
+            "[ESC] Quit".to_string(),
+        ];
+        f.render_widget(
+            TermViz::widget_stats_block("  C O N T R O L S  ", controls),
+            controls_area,
+        );
     }
 
-    fn render_game_canvas(game: &Game) -> impl Widget + '_ {
+    fn draw_play(
+        f: &mut Frame,
+        config: &VizConfig,
+        game: &Game,
+        game_idx: usize,
+        best_score: usize,
+        tick: Duration,
+    ) {
+        let root = Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)]);
+        let stats_vertical =
+            Layout::vertical([Constraint::Percentage(60), Constraint::Percentage(40)]);
+        let [game_area, stats_lane] = root.areas(f.size());
+        let [stats_area, controls_area] = stats_vertical.areas(stats_lane);
+
+        if config.use_game_canvas {
+            f.render_widget(TermViz::render_game_canvas(game, config), game_area);
+        } else {
+            f.render_widget(TermViz::display_game_blocks(game), game_area);
+        }
+
+        let mut items = vec![
+            format!("Game: {}", game_idx + 1),
+            format!("Score: {}/{}", game.score(), game.max_score()),
+            format!("Best: {best_score}"),
+            format!("Steps: {}", game.total_steps),
+        ];
+        if let Some(level) = game.level() {
+            items.push(format!("Level: {}", level.name));
+        }
+        items.push(format!("Board: {}x{}", game.width(), game.height()));
+        items.push(format!("Tick: {} ms", tick.as_millis()));
+        items.push(String::new());
+        items.push(if game.is_dead {
+            format!("Game over, {:?}", game.outcome)
+        } else if game.total_steps == 0 {
+            "Press a direction to start".to_string()
+        } else {
+            "Playing".to_string()
+        });
+        f.render_widget(
+            TermViz::widget_stats_block("  P L A Y  ", items),
+            stats_area,
+        );
+
+        let controls = vec![
+            "[Arrows/WASD] Move".to_string(),
+            "[R] Next game, gives up a running one".to_string(),
+            "[ESC] Quit".to_string(),
+        ];
+        f.render_widget(
+            TermViz::widget_stats_block("  C O N T R O L S  ", controls),
+            controls_area,
+        );
+    }
+
+    fn draw_versus(
+        f: &mut Frame,
+        config: &VizConfig,
+        human: &Game,
+        net: &Game,
+        game_idx: usize,
+        tally: VersusTally,
+        tick: Duration,
+    ) {
+        let root = Layout::vertical([Constraint::Min(0), Constraint::Length(10)]);
+        let games_horizontal =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)]);
+        let bottom_horizontal =
+            Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)]);
+        let [games_lane, bottom_lane] = root.areas(f.size());
+        let [human_area, net_area] = games_horizontal.areas(games_lane);
+        let [stats_area, controls_area] = bottom_horizontal.areas(bottom_lane);
+
+        for (title, game, area) in [
+            ("  Y O U  ", human, human_area),
+            ("  N E T  ", net, net_area),
+        ] {
+            let block = Block::default()
+                .borders(Borders::ALL)
+                .border_type(BorderType::Plain)
+                .title(title.bold().into_centered_line().green());
+            let inner = block.inner(area);
+            f.render_widget(block, area);
+            if config.use_game_canvas {
+                f.render_widget(TermViz::render_game_canvas(game, config), inner);
+            } else {
+                f.render_widget(TermViz::display_game_blocks(game), inner);
+            }
+        }
+
+        let max_score = human.max_score();
+        let status = match (human.is_dead, net.is_dead) {
+            _ if human.total_steps == 0 => "Press a direction to start".to_string(),
+            (true, true) => match human.score().cmp(&net.score()) {
+                Ordering::Greater => "You win!".to_string(),
+                Ordering::Less => "The net wins".to_string(),
+                Ordering::Equal => "Draw".to_string(),
+            },
+            (true, false) => "The net is still playing".to_string(),
+            (false, true) => format!("The net is out, {:?}", net.outcome),
+            (false, false) => "Playing".to_string(),
+        };
+        let items = vec![
+            format!("Game: {}", game_idx + 1),
+            format!(
+                "You: {}/{}, Net: {}/{}",
+                human.score(),
+                max_score,
+                net.score(),
+                max_score
+            ),
+            format!(
+                "Wins - You: {}, Net: {}, Draws: {}",
+                tally.human, tally.net, tally.draws
+            ),
+            format!("Tick: {} ms", tick.as_millis()),
+            String::new(),
+            status,
+        ];
+        f.render_widget(
+            TermViz::widget_stats_block("  V E R S U S  ", items),
+            stats_area,
+        );
+
+        let controls = vec![
+            "[Arrows/WASD] Move".to_string(),
+            "[R] Next game, gives up a running one".to_string(),
+            "[ESC] Quit".to_string(),
+        ];
+        f.render_widget(
+            TermViz::widget_stats_block("  C O N T R O L S  ", controls),
+            controls_area,
+        );
+    }
+
+    fn draw_arena(f: &mut Frame, arena: &Arena, names: &[String], wins: &[usize], round: usize) {
+        let root = Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)]);
+        let stats_vertical =
+            Layout::vertical([Constraint::Percentage(60), Constraint::Percentage(40)]);
+        let [game_area, stats_lane] = root.areas(f.size());
+        let [snakes_area, controls_area] = stats_vertical.areas(stats_lane);
+
+        f.render_widget(TermViz::display_arena_blocks(arena), game_area);
+
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .border_type(BorderType::Plain)
+            .title(
+                format!("  A R E N A    R O U N D  {}  ", round + 1)
+                    .bold()
+                    .into_centered_line()
+                    .green(),
+            );
+        let items: Vec<ListItem> = arena
+            .snakes
+            .iter()
+            .enumerate()
+            .map(|(i, snake)| {
+                let status = if snake.game.is_dead {
+                    format!("{:?}", snake.game.outcome)
+                } else {
+                    "Alive".to_string()
+                };
+                let line = Line::from(vec![
+                    Span::styled(
+                        "██ ",
+                        Style::default().fg(SNAKE_COLORS[i % SNAKE_COLORS.len()]),
+                    ),
+                    Span::raw(format!(
+                        "{}  Score: {}  Wins: {}  {status}",
+                        names[i],
+                        snake.game.score(),
+                        wins[i]
+                    )),
+                ]);
+                ListItem::new(Text::from(line).alignment(Alignment::Center))
+            })
+            .collect();
+        f.render_widget(List::new(items).block(block), snakes_area);
+
+        let controls = vec!["[Space] Pause".to_string(), "[ESC] Quit".to_string()];
+        f.render_widget(
+            TermViz::widget_stats_block("  C O N T R O L S  ", controls),
+            controls_area,
+        );
+    }
+
+    fn render_game_canvas<'a>(game: &'a Game, config: &'a VizConfig) -> impl Widget + 'a {
         Canvas::default()
             .block(Block::new())
             .marker(Marker::HalfBlock)
             .paint(move |ctx| {
-                ctx.draw(&GameRender { game });
+                ctx.draw(&GameRender { game, config });
             })
             .x_bounds([0.0, 100.0])
             .y_bounds([0.0, 100.0])
     }
 
-    fn render_viz_stats(agent: &Agent) -> impl Widget {
-        let title = "  V I Z    S T A T S  ";
-        let current_score = format!(
-            "  Score: {:?}/{:?}",
-            agent.game.score(),
-            ((GRID_SIZE - 1) * (GRID_SIZE - 1))
+    /// Food pickups along the game with a cursor at the current step
+    fn render_timeline(playback: &Playback, width: u16) -> impl Widget {
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .border_type(BorderType::Plain)
+            .title("  T I M E L I N E  ".bold().into_centered_line().yellow());
+
+        let cols = usize::from(width.saturating_sub(2)).max(1);
+        let num_steps = playback.num_steps().max(1);
+        let col = |step: usize| step.min(num_steps) * (cols - 1) / num_steps;
+
+        let mut track = vec![Span::styled("─", Style::default().fg(Color::DarkGray)); cols];
+        for &step in playback.pickups() {
+            track[col(step)] = Span::styled("●", Style::default().fg(COLOR_FOOD));
+        }
+        let cursor = format!("{}▲", " ".repeat(col(playback.step())));
+        let info = format!(
+            "Step: {}/{}, Score: {}   [Left/Right] Step  [Home/End] Jump  [Space] Resume",
+            playback.step(),
+            playback.num_steps(),
+            playback.game().score()
         );
-        let fitness = format!("Fitness: {:.2?}", agent.fitness());
+
+        Paragraph::new(vec![
+            Line::from(track),
+            Line::styled(cursor, Style::default().fg(COLOR_HEAD)),
+            Line::styled(info, Style::default().fg(Color::DarkGray)),
+        ])
+        .block(block)
+    }
+
+    fn render_viz_stats(agent: &Agent, fitness: f32, max_score: usize) -> impl Widget {
+        let title = "  V I Z    S T A T S  ";
+        let current_score = format!("  Score: {:?}/{:?}", agent.game.score(), max_score);
+        let fitness = format!("Fitness: {:.2?}", fitness);
         let fsteps = format!(
             "FSteps: {:?}/{:?}",
             agent.game.no_food_steps,
@@ -240,17 +710,27 @@ impl TermViz {
         sim_start_ts: &Instant,
         mutation_rate: f64,
         mutation_magnitude: f64,
+        max_score: usize,
+        num_stages: usize,
     ) -> impl Widget {
         let title = "  S I M    S T A T S  ";
         let elapsed = sim_start_ts.elapsed().as_secs_f32() / 60.0;
-        let max_score = (GRID_SIZE - 1) * (GRID_SIZE - 1);
         let items = vec![
             format!("Gen: {0}", stats.gen_count),
             format!("Sim Max: {0}/{1}", stats.sim_max_score, max_score),
             format!("Gen Max: {0}/{1}", stats.gen_max_score, max_score),
             format!("Mutation Rate: {0}", mutation_rate),
             format!("Mutation Magnitude: {0}", mutation_magnitude),
-            format!("Gen Max: {0}/{1}", stats.gen_max_score, max_score),
+            match stats.curriculum_stage {
+                Some(stage) => format!(
+                    "Board: {}x{}, Stage: {}/{}",
+                    stats.board_width,
+                    stats.board_height,
+                    stage + 1,
+                    num_stages
+                ),
+                None => format!("Board: {}x{}", stats.board_width, stats.board_height),
+            },
             format!("Gen Ts: {:.2} secs", stats.time_elapsed_secs),
             format!("Sim Ts: {:.2} mins", elapsed),
         ];
@@ -258,21 +738,60 @@ impl TermViz {
         TermViz::widget_stats_block(title, items)
     }
 
-    fn render_about() -> impl Widget {
+    fn render_about(config: &SimConfig) -> impl Widget {
         let title = "  S N A K E   A I  ";
         let items = vec![
-            format!("Num Agents: {NUM_AGENTS}"),
-            format!("Step Limit: {NUM_STEPS}"),
-            format!("Net Arch: {:?}", NN_ARCH),
-            format!("Save Net: {IS_SAVE_BEST_NET}"),
-            format!("Load Net: {IS_LOAD_SAVED_DATA}"),
+            format!("Num Agents: {}", config.sim.num_agents),
+            format!("Step Limit: {}", config.sim.num_steps),
+            format!("Net Arch: {:?}", config.nn.arch),
+            format!(
+                "Seed: {}",
+                config.sim.seed.map_or("-".to_string(), |s| s.to_string())
+            ),
+            format!("Save Net: {}", config.data.is_save_best_net),
+            format!("Load Net: {}", config.data.is_load_saved_data),
             String::new(),
+            "[Space] Pause, [Left/Right] Step".to_string(),
             "Press [ESC] to quit".to_string(),
         ];
 
         TermViz::widget_stats_block(title, items)
     }
 
+    fn render_outcomes(outcomes: &OutcomeCounts) -> impl Widget {
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .border_type(BorderType::Plain)
+            .title("  D E A T H S  ".bold().into_centered_line().yellow());
+
+        let total = (outcomes.deaths() + outcomes.won).max(1);
+        let bar = |label: &'static str, count: usize, color: Color| {
+            Bar::default()
+                .label(label.into())
+                .value(count as u64)
+                .text_value(format!(
+                    "{count} ({:.0}%)",
+                    count as f64 * 100.0 / total as f64
+                ))
+                .style(Style::default().fg(color))
+                .value_style(Style::default().fg(Color::Black).bg(color))
+        };
+        let bars = [
+            bar("Wall", outcomes.hit_wall, COLOR_WALLS),
+            bar("Self", outcomes.hit_self, COLOR_BODY),
+            bar("Starved", outcomes.starved, COLOR_DEAD),
+            bar("Won", outcomes.won, COLOR_FOOD),
+        ];
+
+        BarChart::default()
+            .block(block)
+            .direction(Direction::Horizontal)
+            .bar_width(1)
+            .bar_gap(0)
+            .max(total as u64)
+            .data(BarGroup::default().bars(&bars))
+    }
+
     fn render_score_graph(data: &[u64]) -> impl Widget + '_ {
         TermViz::widget_sparkline(data, "  G E N    S C O R E S  ", Color::LightGreen)
     }
@@ -281,15 +800,15 @@ impl TermViz {
         TermViz::widget_sparkline(data, "  G E N    T I M E S  ", Color::LightCyan)
     }
 
-    fn render_viz_score_gauge(score: usize) -> impl Widget {
-        let ratio = score as f64 / f64::from((GRID_SIZE - 1) * (GRID_SIZE - 1));
+    fn render_viz_score_gauge(score: usize, max_score: usize) -> impl Widget {
+        let ratio = score as f64 / max_score as f64;
         let ratio = ratio.clamp(0.0, 1.0);
         let title = "  V I Z    S C O R E  ";
         TermViz::widget_gauge(ratio, title, Color::LightMagenta)
     }
 
-    fn render_max_score_gauge(score: usize) -> impl Widget {
-        let ratio = score as f64 / f64::from((GRID_SIZE - 1) * (GRID_SIZE - 1));
+    fn render_max_score_gauge(score: usize, max_score: usize) -> impl Widget {
+        let ratio = score as f64 / max_score as f64;
         let ratio = ratio.clamp(0.0, 1.0);
         let title = "  M A X    S C O R E  ";
         TermViz::widget_gauge(ratio, title, Color::LightRed)
@@ -336,9 +855,11 @@ impl TermViz {
     }
 
     fn get_simple_render_text(viz: &VizData) -> String {
-        let max_score = (GRID_SIZE - 1) * (GRID_SIZE - 1);
+        let max_score = viz
+            .watched_agent()
+            .map_or_else(|| viz.config.game.max_score(), |a| a.game.max_score());
         let mut message = format!(
-            "Gen: {:?}, Max: {:?}/{:?}, Gen_Max: {:?}/{:?}, Ts: {:.2?}, Sim_Ts: {:.2?}\nMR: {:.2?}, MG: {:.2?}\n\n",
+            "Gen: {:?}, Max: {:?}/{:?}, Gen_Max: {:?}/{:?}, Ts: {:.2?}, Sim_Ts: {:.2?}\nMR: {:.2?}, MG: {:.2?}\nWall: {:?}, Self: {:?}, Starved: {:?}, Won: {:?}\n\n",
             viz.stats.gen_count,
             viz.stats.sim_max_score,
             max_score,
@@ -347,11 +868,15 @@ impl TermViz {
             viz.stats.time_elapsed_secs,
             (viz.sim_start_ts.elapsed().as_secs_f32() / 60.0),
             viz.mutation_rate,
-            viz.mutation_magnitude
+            viz.mutation_magnitude,
+            viz.stats.outcomes.hit_wall,
+            viz.stats.outcomes.hit_self,
+            viz.stats.outcomes.starved,
+            viz.stats.outcomes.won,
         );
 
         // Game Render
-        if let Some(agent) = &viz.agent {
+        if let Some(agent) = viz.watched_agent() {
             let game = &agent.game;
             message.push_str(&TermViz::get_block_game_string(game));
 
@@ -361,8 +886,8 @@ impl TermViz {
                 format!(
                     "Score: {:?}/{:?}, Fitness: {:.2?}, FSteps: {:?}/{:?}\n",
                     agent.game.score(),
-                    ((GRID_SIZE - 1) * (GRID_SIZE - 1)),
-                    agent.fitness(),
+                    max_score,
+                    viz.fitness_fn.fitness(&agent.game),
                     agent.game.no_food_steps,
                     agent.get_step_limit()
                 )
@@ -375,8 +900,8 @@ impl TermViz {
 
     fn get_block_game_string(game: &Game) -> String {
         let mut game_grid = String::new();
-        for x in 0..=GRID_SIZE {
-            for y in 0..=GRID_SIZE {
+        for x in 0..=game.width() {
+            for y in 0..=game.height() {
                 let pt = (x, y).into();
                 if game.food == pt {
                     game_grid.push_str("▒▒");
@@ -446,7 +971,7 @@ impl TermViz {
         let mut inp_colors = Vec::new();
         for (i, val) in nn_input.iter().enumerate() {
             // These are 1-hot encoded head and tail directions
-            if i >= (NN_ARCH[0] - 8) {
+            if i >= (agent.brain.num_inputs() - 8) {
                 if *val >= 1.0 {
                     inp_colors.push(Color::Cyan);
                 } else {
@@ -525,13 +1050,32 @@ impl TermViz {
         }
     }
 
+    // The diagram is drawn for the shape of the net being watched, not the config
     fn render_nn(agent: &Agent) -> impl Widget {
-        if NN_ARCH != [24, 16, 8, 4] {
+        let meta = agent.brain.meta();
+        if agent.brain.layer_sizes() != [24, 16, 8, 4]
+            || meta.actions != ActionSpace::Absolute
+            || meta.sensors != SensorKind::default_suite()
+        {
             let block = Block::default()
                 .borders(Borders::ALL)
                 .border_type(BorderType::Plain);
-            return Paragraph::new("Can only visualize network with arch [24, 16, 8, 4]")
-                .block(block);
+            let values = |vals: &[f64]| {
+                vals.iter()
+                    .map(|v| format!("{v:.2}"))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            };
+            let inputs = agent.get_brain_input();
+            let outputs = agent.brain.predict(inputs.clone());
+            let text = vec![
+                Line::from("Can only visualize network with arch [24, 16, 8, 4], absolute actions and the default sensors"),
+                Line::from(""),
+                Line::from(format!("Inputs: {}", values(&inputs))),
+                Line::from(format!("Outputs: {}", values(&outputs))),
+                Line::from(format!("Move: {:?}", agent.get_brain_output())),
+            ];
+            return Paragraph::new(text).wrap(Wrap { trim: true }).block(block);
         }
 
         let network: Vec<Vec<&str>> = TermViz::get_network_text();
@@ -596,9 +1140,9 @@ impl TermViz {
         let body_color = if game.is_dead { COLOR_DEAD } else { COLOR_BODY };
         let head_color = if game.is_dead { COLOR_DEAD } else { COLOR_HEAD };
 
-        for x in 0..=GRID_SIZE {
+        for x in 0..=game.width() {
             let mut line_spans = Vec::new();
-            for y in 0..=GRID_SIZE {
+            for y in 0..=game.height() {
                 let pt = (x, y).into();
                 if game.food == pt {
                     line_spans.push(Span::styled("██", Style::default().fg(COLOR_FOOD)));
@@ -624,12 +1168,42 @@ impl TermViz {
         let block = Block::default().padding(Padding::new(8, 0, 8, 0));
         Paragraph::new(lines).block(block)
     }
+
+    // Same layout as the game blocks, a color per snake
+    fn display_arena_blocks(arena: &Arena) -> impl Widget {
+        let board = &arena.snakes[0].game;
+        let mut lines = Vec::new();
+        for x in 0..=board.width() {
+            let mut line_spans = Vec::new();
+            for y in 0..=board.height() {
+                let pt = (x, y).into();
+                let color = match arena.snake_at(pt) {
+                    _ if arena.food == pt => Some(COLOR_FOOD),
+                    _ if board.is_wall(pt) => Some(COLOR_WALLS),
+                    Some((_, true)) => Some(COLOR_HEAD),
+                    Some((i, false)) => Some(SNAKE_COLORS[i % SNAKE_COLORS.len()]),
+                    None => None,
+                };
+                line_spans.push(match color {
+                    Some(color) => Span::styled("██", Style::default().fg(color)),
+                    None => Span::styled("  ", Style::default()),
+                });
+            }
+            lines.push(Line::from(line_spans));
+        }
+
+        let block = Block::default().padding(Padding::new(8, 0, 8, 0));
+        Paragraph::new(lines).block(block)
+    }
 }
 
 impl<'a> GameRender<'a> {
     fn draw_rect(&self, painter: &mut Painter, point: Point, color: Color) {
-        let scale = VIZ_GAME_SCALE;
-        let offset = ((VIZ_OFFSET) * scale, (VIZ_OFFSET + 2) * scale);
+        let scale = self.config.game_scale;
+        let offset = (
+            (self.config.offset) * scale,
+            (self.config.offset + 2) * scale,
+        );
         for dx in 0..scale {
             for dy in 0..scale {
                 let x = point.x * scale + dx + offset.0;
@@ -639,11 +1213,13 @@ impl<'a> GameRender<'a> {
         }
     }
 
-    fn draw_border(&self, painter: &mut Painter) {
-        for i in 0..=GRID_SIZE {
-            for j in 0..=GRID_SIZE {
-                if i == 0 || i == GRID_SIZE || j == 0 || j == GRID_SIZE {
-                    self.draw_rect(painter, Point { x: i, y: j }, COLOR_WALLS);
+    fn draw_walls(&self, painter: &mut Painter) {
+        let (width, height) = (self.game.width(), self.game.height());
+        for i in 0..=width {
+            for j in 0..=height {
+                let pt = Point { x: i, y: j };
+                if self.game.is_wall(pt) {
+                    self.draw_rect(painter, pt, COLOR_WALLS);
                 }
             }
         }
@@ -673,16 +1249,19 @@ impl<'a> GameRender<'a> {
 
 impl Shape for GameRender<'_> {
     fn draw(&self, painter: &mut Painter) {
-        self.draw_border(painter);
+        self.draw_walls(painter);
         self.draw_snake(painter);
         self.draw_food(painter);
     }
 }
 
-impl Default for VizData {
-    fn default() -> Self {
+impl VizData {
+    fn new(config: &SimConfig) -> Self {
         Self {
+            config: config.clone(),
+            fitness_fn: config.pop.fitness.build(),
             agent: None,
+            scrubber: None,
             stats: GenerationSummary::default(),
             sim_start_ts: Instant::now(),
             scores: Vec::new(),
@@ -691,4 +1270,45 @@ impl Default for VizData {
             mutation_rate: 0.0,
         }
     }
+
+    /// The agent at the selected step while scrubbing, else the live one
+    fn watched_agent(&self) -> Option<&Agent> {
+        self.scrubber
+            .as_ref()
+            .map(|scrubber| &scrubber.agent)
+            .or(self.agent.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::nn::Activation;
+    use crate::SimRng;
+
+    fn render(arch: &[usize], activations: &[Activation]) {
+        let config = SimConfig::default();
+        let mut rng = SimRng::seed_from_u64(7);
+        let net = Net::new(arch, activations, &mut rng).with_meta(config.nn.meta());
+        let agent = Agent::with_brain(net, &config, 7);
+
+        let area = Rect::new(0, 0, 60, 40);
+        let mut buf = Buffer::empty(area);
+        TermViz::render_nn(&agent).render(area, &mut buf);
+    }
+
+    #[test]
+    fn nn_diagram_follows_the_watched_net() {
+        render(
+            &[24, 16, 8, 4],
+            &[Activation::Relu, Activation::Relu, Activation::Sigmoid],
+        );
+        render(&[24, 16, 4], &[Activation::Relu, Activation::Sigmoid]);
+        render(
+            &[24, 8, 16, 4],
+            &[Activation::Relu, Activation::Relu, Activation::Sigmoid],
+        );
+    }
 }